@@ -22,24 +22,33 @@ use base64::prelude::*;
 use bevy_pkv::PkvStore;
 use serde::{Deserialize, Serialize};
 
+use crate::blacklist::Blacklist;
+use crate::filters::{self, Filters};
+use crate::keymap::{self, Action, Keymap};
+use crate::outbox;
+use crate::pool;
+use crate::profile;
+use crate::query::parse_all;
+use crate::saved;
+use crate::secret;
 use crate::tag;
-use crate::yiff::Yiff;
+use crate::timers;
+use crate::yiff::{Choice, Order, Pool, PostSet, Problem, Snapshot, TagCategory, Yiff};
 
 use dioxus::prelude::*;
-use keyboard_types::Key;
 
-use rs621::post::{Post, PostFileExtension, Query};
+use rs621::post::{Post, PostFileExtension, PostRating};
 
+use std::any::Any;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::lock::Mutex;
 
 use url::Url;
 
-const BASE_URL: &str = "https://e621.net";
-
 lazy_static::lazy_static! {
     static ref LOGO_E621: String = format!(
         "data:image/svg+xml;base64,{}",
@@ -47,20 +56,48 @@ lazy_static::lazy_static! {
     );
 }
 
+/// Posts fetched before they're shown, so page boundaries don't stall.
+const LOOKAHEAD: usize = 10;
+
+/// Posts seen that a saved session remembers, so it doesn't grow forever.
+const SESSION_LIMIT: usize = 1000;
+
+/// How long after a swipe the session is saved. Swiping quickly saves once
+/// things settle, instead of after every post.
+const SESSION_SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// Upcoming posts whose media is preloaded.
+const PRELOAD: usize = 3;
+
 static ERRORS: GlobalSignal<Vec<String>> = GlobalSignal::new(Vec::new);
 
-trait ResultExt<O> {
+/// Why the user was sent back to (or kept on) the login screen.
+static REJECTED: GlobalSignal<Option<String>> = GlobalSignal::new(|| None);
+
+/// Logs the user out if `error` means their credentials stopped working.
+pub(crate) fn note_rejection(error: &crate::yiff::Error) {
+    if error.problem() == Problem::Unauthorized {
+        *REJECTED.write() = Some(format!(
+            "Your API key was rejected, so you've been logged out. ({error})"
+        ));
+    }
+}
+
+pub(crate) trait ResultExt<O> {
     fn record_err(self) -> Option<O>;
 }
 
 impl<O, E> ResultExt<O> for Result<O, E>
 where
-    E: ToString,
+    E: ToString + 'static,
 {
     fn record_err(self) -> Option<O> {
         match self {
             Ok(o) => Some(o),
             Err(e) => {
+                if let Some(e) = (&e as &dyn Any).downcast_ref::<crate::yiff::Error>() {
+                    note_rejection(e);
+                }
                 ERRORS.write().push(e.to_string());
                 None
             }
@@ -71,22 +108,39 @@ where
 #[derive(Debug, Clone)]
 struct Search {
     search: Arc<Mutex<crate::yiff::Search>>,
-    yiff: Yiff,
 }
 
 impl Search {
-    pub fn new<Q: Into<Query>>(yiff: Yiff, query: Q) -> Self {
-        let query = query.into();
-        let search = yiff.search(query);
+    pub fn new<I, S>(yiff: Yiff, terms: I, order: Order) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::resume(yiff, terms, order, Snapshot::default())
+    }
+
+    pub fn resume<I, S>(yiff: Yiff, terms: I, order: Order, snapshot: Snapshot) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let search = yiff.resume(terms, order, snapshot);
+
+        Search {
+            search: Arc::new(Mutex::new(search)),
+        }
+    }
+
+    pub fn read(yiff: Yiff, pool: Pool, snapshot: Snapshot) -> Self {
+        let search = yiff.read(pool, snapshot);
 
         Search {
             search: Arc::new(Mutex::new(search)),
-            yiff,
         }
     }
 }
 
-fn store() -> PkvStore {
+pub(crate) fn store() -> PkvStore {
     PkvStore::new_with_qualifier("fun", "yiffy", env!("CARGO_PKG_NAME"))
 }
 
@@ -100,41 +154,503 @@ enum Hand {
 impl Hand {
     async fn load() -> Self {
         // TODO: Find a spawn_blocking replacement.
-        store().get("hand").unwrap_or_default()
+        store().get(&profile::key("hand")).unwrap_or_default()
     }
 
     async fn save(self) {
         // TODO: Find a spawn_blocking replacement.
-        store().set("hand", &self).record_err();
+        store().set(&profile::key("hand"), &self).record_err();
+    }
+}
+
+/// How pools are shown.
+#[derive(Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+enum Layout {
+    /// One page at a time, fit to the screen, like any other search.
+    #[default]
+    Swipe,
+    /// Pages fill the width, and tall ones scroll vertically.
+    Reader,
+}
+
+impl Layout {
+    async fn load() -> Self {
+        // TODO: Find a spawn_blocking replacement.
+        store().get(&profile::key("layout")).unwrap_or_default()
+    }
+
+    async fn save(self) {
+        // TODO: Find a spawn_blocking replacement.
+        store().set(&profile::key("layout"), &self).record_err();
+    }
+}
+
+/// Which e621-family site to talk to.
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+enum Instance {
+    #[default]
+    E621,
+    E926,
+    E6ai,
+    Custom(String),
+}
+
+impl Instance {
+    const PRESETS: [Instance; 3] = [Instance::E621, Instance::E926, Instance::E6ai];
+
+    fn base_url(&self) -> &str {
+        match self {
+            Instance::E621 => "https://e621.net",
+            Instance::E926 => "https://e926.net",
+            Instance::E6ai => "https://e6ai.net",
+            Instance::Custom(url) => url.trim_end_matches('/'),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Instance::E621 => "e621",
+            Instance::E926 => "e926",
+            Instance::E6ai => "e6ai",
+            Instance::Custom(url) => url,
+        }
+    }
+
+    /// Value used for this instance in `<select>` elements.
+    fn id(&self) -> &'static str {
+        match self {
+            Instance::E621 => "e621",
+            Instance::E926 => "e926",
+            Instance::E6ai => "e6ai",
+            Instance::Custom(_) => "custom",
+        }
+    }
+
+    fn from_id(id: &str) -> Self {
+        match id {
+            "e926" => Instance::E926,
+            "e6ai" => Instance::E6ai,
+            "custom" => Instance::Custom(String::new()),
+            _ => Instance::E621,
+        }
+    }
+
+    /// Custom instances have to use HTTPS, so the API key isn't sent in the
+    /// clear. Plain HTTP is only allowed for one running on this machine.
+    fn is_valid(&self) -> bool {
+        let url = match self {
+            Instance::Custom(url) => url,
+            _ => return true,
+        };
+
+        match Url::parse(url) {
+            Ok(u) if u.scheme() == "https" => u.has_host(),
+            Ok(u) if u.scheme() == "http" => {
+                matches!(u.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"))
+            }
+            _ => false,
+        }
+    }
+
+    /// Joins `path` onto the instance's base URL.
+    fn url(&self, path: &str) -> Option<Url> {
+        Url::parse(&format!("{}{}", self.base_url(), path)).ok()
     }
 }
 
-#[derive(Props, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[derive(Props, Clone, Eq, PartialEq, Default)]
 struct Credentials {
     username: String,
     api_key: String,
     active: bool,
+    instance: Instance,
+    /// Never saved. Used to seal the API key when saving.
+    passphrase: Option<String>,
+    /// The API key, while it waits for a passphrase to unseal it.
+    locked: Option<secret::Sealed>,
+}
+
+/// [`Credentials`] as they're saved, with the API key sealed.
+#[derive(Serialize, Deserialize)]
+struct StoredCredentials {
+    username: String,
+    api_key: secret::Stored,
+    active: bool,
+    #[serde(default)]
+    instance: Instance,
 }
 
 impl Credentials {
+    fn key_for(instance: &Instance) -> String {
+        profile::key(&format!("credentials:{}", instance.base_url()))
+    }
+
+    /// Every instance with credentials saved under [`Credentials::key_for`],
+    /// since the store can't list its keys.
+    fn saved_instances() -> Vec<Instance> {
+        // TODO: Find a spawn_blocking replacement.
+        store()
+            .get(&profile::key("credential-instances"))
+            .unwrap_or_default()
+    }
+
+    /// Instances that might have credentials saved: the presets, for keys
+    /// saved before instances were remembered, and every remembered one.
+    fn instances() -> Vec<Instance> {
+        let mut instances = Instance::PRESETS.to_vec();
+        for instance in Self::saved_instances() {
+            if !instances.contains(&instance) {
+                instances.push(instance);
+            }
+        }
+        instances
+    }
+
+    /// Seals every API key saved before they were encrypted.
+    fn seal_plain_keys() {
+        for instance in Self::instances() {
+            let key = Self::key_for(&instance);
+
+            // TODO: Find a spawn_blocking replacement.
+            let Ok(stored) = store().get::<StoredCredentials>(&key) else {
+                continue;
+            };
+
+            let secret::Stored::Plain(api_key) = &stored.api_key else {
+                continue;
+            };
+
+            if let Some(sealed) = secret::seal(api_key, None).record_err() {
+                let stored = StoredCredentials {
+                    api_key: secret::Stored::Sealed(sealed),
+                    ..stored
+                };
+                store().set(&key, &stored).record_err();
+            }
+        }
+    }
+
+    fn remember_instance(store: &mut PkvStore, instance: &Instance) {
+        let mut instances = Self::saved_instances();
+        if instances.contains(instance) {
+            return;
+        }
+
+        instances.push(instance.clone());
+        store
+            .set(&profile::key("credential-instances"), &instances)
+            .record_err();
+    }
+
+    fn unseal(stored: StoredCredentials, passphrase: Option<String>) -> Self {
+        let mut creds = Credentials {
+            username: stored.username,
+            active: stored.active,
+            instance: stored.instance,
+            ..Default::default()
+        };
+
+        match stored.api_key {
+            secret::Stored::Plain(api_key) => creds.api_key = api_key,
+            secret::Stored::Sealed(sealed) => {
+                match secret::open(&sealed, passphrase.as_deref()) {
+                    Ok(api_key) => creds.api_key = api_key,
+                    Err(_) if sealed.passphrase => creds.locked = Some(sealed),
+                    Err(e) => {
+                        // The device key is gone, so log in again.
+                        ERRORS.write().push(e.to_string());
+                        creds.active = false;
+                    }
+                }
+            }
+        }
+
+        creds.passphrase = passphrase;
+        creds
+    }
+
+    async fn load() -> Self {
+        Self::seal_plain_keys();
+
+        // TODO: Find a spawn_blocking replacement.
+        let stored: StoredCredentials = match store().get(&profile::key("credentials")) {
+            Ok(s) => s,
+            Err(_) => return Default::default(),
+        };
+
+        let plain = matches!(stored.api_key, secret::Stored::Plain(_));
+        let creds = Self::unseal(stored, None);
+
+        if plain {
+            // Saved before API keys were encrypted, so seal it now.
+            creds.clone().save().await;
+        }
+
+        creds
+    }
+
+    /// Loads the credentials last used with `instance`, if there are any.
+    async fn load_for(instance: Instance, passphrase: Option<String>) -> Self {
+        // TODO: Find a spawn_blocking replacement.
+        let stored = match store().get(&Self::key_for(&instance)) {
+            Ok(s) => s,
+            Err(_) => {
+                return Credentials {
+                    instance,
+                    passphrase,
+                    ..Default::default()
+                }
+            }
+        };
+
+        let mut creds = Self::unseal(stored, passphrase);
+        if creds.locked.take().is_some() {
+            // Sealed with some other passphrase. Log in again to fix it.
+            creds.active = false;
+        }
+        creds
+    }
+
+    fn seal(&self) -> Option<StoredCredentials> {
+        let sealed = secret::seal(&self.api_key, self.passphrase.as_deref()).record_err()?;
+        Some(StoredCredentials {
+            username: self.username.clone(),
+            api_key: secret::Stored::Sealed(sealed),
+            active: self.active,
+            instance: self.instance.clone(),
+        })
+    }
+
+    /// Saves these credentials as the ones for their instance, but without
+    /// switching to them.
+    async fn save_for_instance(self) {
+        if self.locked.is_some() {
+            return;
+        }
+
+        if let Some(stored) = self.seal() {
+            // TODO: Find a spawn_blocking replacement.
+            let mut store = store();
+            store
+                .set(&Self::key_for(&self.instance), &stored)
+                .record_err();
+            Self::remember_instance(&mut store, &self.instance);
+        }
+    }
+
+    async fn save(self) {
+        if self.locked.is_some() {
+            return;
+        }
+
+        if let Some(stored) = self.seal() {
+            // TODO: Find a spawn_blocking replacement.
+            let mut store = store();
+            store
+                .set(&Self::key_for(&self.instance), &stored)
+                .record_err();
+            store
+                .set(&profile::key("credentials"), &stored)
+                .record_err();
+            Self::remember_instance(&mut store, &self.instance);
+        }
+    }
+
+    /// Unseals the API key with `passphrase`, which is kept for saving.
+    fn unlock(&mut self, passphrase: String) -> Result<(), secret::Error> {
+        if let Some(sealed) = &self.locked {
+            self.api_key = secret::open(sealed, Some(&passphrase))?;
+        }
+
+        self.locked = None;
+        self.passphrase = Some(passphrase);
+        Ok(())
+    }
+
+    /// Seals every saved API key with `passphrase` instead of the current
+    /// one. `None` removes the passphrase.
+    async fn change_passphrase(&mut self, passphrase: Option<String>) {
+        let mut stuck = Vec::new();
+
+        for instance in Self::instances() {
+            if instance == self.instance {
+                continue;
+            }
+
+            // TODO: Find a spawn_blocking replacement.
+            let stored = match store().get(&Self::key_for(&instance)) {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+
+            let mut other = Self::unseal(stored, self.passphrase.clone());
+            if other.locked.is_some() {
+                // Sealed with some other passphrase, so it can't be opened.
+                stuck.push(instance.name().to_owned());
+                continue;
+            }
+
+            if other.api_key.is_empty() {
+                continue;
+            }
+
+            other.passphrase = passphrase.clone();
+            other.save_for_instance().await;
+        }
+
+        if !stuck.is_empty() {
+            ERRORS.write().push(format!(
+                "Your current passphrase doesn't unlock the API key for {}, so it \
+                 still needs the old one. Log in there again to fix it.",
+                stuck.join(", "),
+            ));
+        }
+
+        self.passphrase = passphrase;
+        self.clone().save().await;
+    }
+
+    /// Forgets the API key for this instance, keeping the choice of instance.
+    fn logged_out(&self) -> Self {
+        Credentials {
+            instance: self.instance.clone(),
+            passphrase: self.passphrase.clone(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(not(feature = "offline"))]
+    fn yiff(&self) -> Yiff {
+        Yiff::new(
+            self.instance.base_url(),
+            "pkk@tabby.rocks",
+            &self.username,
+            &self.api_key,
+        )
+        .with_history_limit(crate::platform::HISTORY_LIMIT)
+        .with_lookahead(LOOKAHEAD)
+    }
+
+    #[cfg(feature = "offline")]
+    fn yiff(&self) -> Yiff {
+        Yiff::with_backend(crate::yiff::memory::Memory::new())
+            .with_history_limit(crate::platform::HISTORY_LIMIT)
+            .with_lookahead(LOOKAHEAD)
+    }
+
+    /// Explains why these credentials didn't work.
+    fn problem(&self, error: &crate::yiff::Error) -> String {
+        let site = self.instance.name();
+        if let crate::yiff::Error::NoAccount { .. } = error {
+            return format!("There's no account named \"{}\" on {site}.", self.username);
+        }
+
+        match error.problem() {
+            Problem::Unauthorized => {
+                format!("That API key doesn't belong to \"{}\".", self.username)
+            }
+            Problem::RateLimited => {
+                format!("{site} is getting too many requests. Wait a moment, then try again.")
+            }
+            Problem::Network => {
+                format!("Couldn't reach {site}. Check your connection, then try again.")
+            }
+            Problem::NotFound | Problem::Other => error.to_string(),
+        }
+    }
+}
+
+/// The blacklist as the user typed (or imported) it.
+#[derive(Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+struct BlacklistRules(String);
+
+impl BlacklistRules {
     async fn load() -> Self {
         // TODO: Find a spawn_blocking replacement.
-        store().get("credentials").unwrap_or_default()
+        store().get(&profile::key("blacklist")).unwrap_or_default()
     }
 
     async fn save(self) {
         // TODO: Find a spawn_blocking replacement.
-        store().set("credentials", &self).record_err();
+        store().set(&profile::key("blacklist"), &self).record_err();
+    }
+
+    fn parse(&self) -> Blacklist {
+        Blacklist::parse(&self.0)
     }
 }
 
 #[derive(Debug, Default)]
 struct ActiveQuery {
     terms: Vec<String>,
+    order: Order,
+    /// Keeps `order:random` in the same order when resuming.
+    seed: u64,
+    /// Where to pick up an earlier session, instead of starting fresh.
+    resume: Option<Snapshot>,
+    /// Read through this pool in order, instead of searching for `terms`.
+    pool: Option<Pool>,
+    /// Bumped when the terms change mid-search, to start the viewer over.
+    restarts: u64,
+    /// The saved search being run, if that's how this search started.
+    saved: Option<u64>,
+    /// Used instead of the defaults, for saved searches with their own.
+    filters: Option<Filters>,
     active: bool,
     message: Option<String>,
 }
 
+/// The most recent search, saved as the user swipes through it.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Session {
+    terms: Vec<String>,
+    seed: u64,
+    snapshot: Snapshot,
+    #[serde(default)]
+    pool: Option<Pool>,
+    #[serde(default)]
+    filters: Option<Filters>,
+    #[serde(default)]
+    order: Order,
+}
+
+/// A [`Session`] waiting for [`SESSION_SAVE_DELAY`] to pass.
+struct Unsaved {
+    session: Session,
+    /// The saved search being run, and how many posts have come up.
+    run: Option<(u64, usize)>,
+}
+
+impl Unsaved {
+    async fn save(self) {
+        if let Some((id, posts)) = self.run {
+            saved::record_run(id, posts);
+        }
+        self.session.save().await;
+    }
+}
+
+impl Session {
+    async fn load() -> Option<Self> {
+        // TODO: Find a spawn_blocking replacement.
+        store().get(&profile::key("session")).ok().flatten()
+    }
+
+    async fn save(self) {
+        // TODO: Find a spawn_blocking replacement.
+        store()
+            .set(&profile::key("session"), &Some(self))
+            .record_err();
+    }
+
+    async fn clear() {
+        // TODO: Find a spawn_blocking replacement.
+        store()
+            .set(&profile::key("session"), &Option::<Self>::None)
+            .record_err();
+    }
+}
+
 pub(crate) fn app() -> Element {
     // Prevent scrolling with keyboard:
     use_future(|| {
@@ -151,6 +667,27 @@ pub(crate) fn app() -> Element {
         .join()
     });
 
+    let profiles = use_resource(profile::load);
+    if profiles.read().is_none() {
+        return rsx! { "Loading profiles..." };
+    }
+
+    match *profile::ACTIVE.read() {
+        None => rsx! {
+            profile::Chooser {}
+            crate::app::notice {}
+        },
+        Some(id) => rsx! {
+            crate::app::home { key: "{id}" }
+        },
+    }
+}
+
+/// Everything for one profile, mounted again whenever the profile changes.
+fn home() -> Element {
+    use_hook(outbox::load);
+    use_hook(saved::load);
+
     let hand = use_resource(Hand::load);
     let hand = match *hand.read_unchecked() {
         Some(a) => a,
@@ -163,9 +700,81 @@ pub(crate) fn app() -> Element {
         None => return rsx! { "Loading credentials..." },
     };
 
+    let layout = use_resource(Layout::load);
+    let layout = match *layout.read_unchecked() {
+        Some(a) => a,
+        None => return rsx! { "Loading layout..." },
+    };
+
+    let blacklist = use_resource(BlacklistRules::load);
+    let blacklist = match &*blacklist.read_unchecked() {
+        Some(a) => a.clone(),
+        None => return rsx! { "Loading blacklist..." },
+    };
+
+    let filters = use_resource(Filters::load);
+    let filters = match &*filters.read_unchecked() {
+        Some(a) => a.clone(),
+        None => return rsx! { "Loading filters..." },
+    };
+
+    let keymap = use_resource(Keymap::load);
+    let keymap = match &*keymap.read_unchecked() {
+        Some(a) => a.clone(),
+        None => return rsx! { "Loading keys..." },
+    };
+
     let mut query = use_signal(ActiveQuery::default);
+    let session = use_resource(move || async move {
+        // Reload whenever the viewer is closed.
+        let _ = query.read().active;
+        Session::load().await
+    });
     let mut credentials_signal = use_signal(|| credentials.clone());
     let mut hand_signal = use_signal(|| hand);
+    let mut layout_signal = use_signal(|| layout);
+    let mut blacklist_signal = use_signal(|| blacklist.clone());
+    let mut filters_signal = use_signal(|| filters.clone());
+    let mut keymap_signal = use_signal(|| keymap.clone());
+    let mut order_signal = use_signal(Order::default);
+
+    let mut yiff = use_signal(|| credentials_signal.peek().yiff());
+
+    use_effect(move || {
+        let creds = credentials_signal.read();
+        if creds.active {
+            yiff.set(creds.yiff());
+        }
+    });
+
+    // Back to the login screen if the credentials stop working.
+    use_effect(move || {
+        if REJECTED.read().is_none() || !credentials_signal.peek().active {
+            return;
+        }
+
+        let mut creds = credentials_signal.write();
+        creds.active = false;
+        spawn_forever(creds.clone().save());
+        query.write().active = false;
+    });
+
+    use_future(move || {
+        outbox::run(move || {
+            let creds = credentials_signal.peek();
+            if !creds.active {
+                return None;
+            }
+            Some((yiff.peek().clone(), creds.instance.base_url().to_owned()))
+        })
+    });
+
+    if credentials_signal.read().locked.is_some() {
+        return rsx! {
+            crate::app::unlock { credentials: credentials_signal }
+            crate::app::notice {}
+        };
+    }
 
     if !credentials_signal.read().active {
         return rsx! {
@@ -174,11 +783,6 @@ pub(crate) fn app() -> Element {
         };
     }
 
-    let yiff = use_signal(|| {
-        let creds = credentials_signal.read();
-        Yiff::new(BASE_URL, "pkk@tabby.rocks", &creds.username, &creds.api_key)
-    });
-
     let options_style = include_str!("options.css");
     let set_hand = move |e: Event<FormData>| {
         let mut signal = hand_signal.write();
@@ -196,6 +800,22 @@ pub(crate) fn app() -> Element {
         spawn_forever(hand.save());
     };
 
+    let set_layout = move |e: Event<FormData>| {
+        let mut signal = layout_signal.write();
+        let layout = match &*e.value() {
+            "swipe" => Layout::Swipe,
+            "reader" => Layout::Reader,
+            _ => unreachable!(),
+        };
+
+        if layout == *signal {
+            return;
+        }
+
+        *signal = layout;
+        spawn_forever(layout.save());
+    };
+
     let errors = ERRORS.read();
     let error_list = if errors.is_empty() {
         None
@@ -213,6 +833,20 @@ pub(crate) fn app() -> Element {
         }
     };
 
+    let resume = session.read().clone().flatten();
+    let resume_label = resume.as_ref().map(|s| match &s.pool {
+        Some(pool) => format!(
+            "Continue reading {} (page {} of {})",
+            pool.title(),
+            s.snapshot.index,
+            pool.post_ids.len()
+        ),
+        None => format!(
+            "Continue where you left off ({} seen)",
+            s.snapshot.seen.len()
+        ),
+    });
+
     let entries = tag::Entries::new();
     match &*query.read_unchecked() {
         q if !q.active => rsx! {
@@ -222,15 +856,98 @@ pub(crate) fn app() -> Element {
                 onsubmit: move |terms| {
                     let mut query = query.write();
                     query.terms = terms;
+                    query.order = order_signal();
+                    query.seed = crate::platform::random_seed();
+                    query.resume = None;
+                    query.pool = None;
+                    query.saved = None;
+                    query.filters = None;
+                    query.active = true;
+                    query.message = None;
+                }
+            }
+            div { class: "order",
+                label {
+                    "Order: "
+                    select {
+                        onchange: move |evt| {
+                            if let Some(order) = Order::from_term(&evt.value()) {
+                                order_signal.set(order);
+                            }
+                        },
+                        for order in Order::ALL {
+                            option {
+                                value: order.term(),
+                                selected: order == order_signal(),
+                                "{order.name()}"
+                            }
+                        }
+                    }
+                }
+            }
+            saved::List {
+                entries,
+                order: order_signal(),
+                defaults: filters_signal(),
+                onlaunch: move |search: saved::SavedSearch| {
+                    saved::record_run(search.id, 0);
+                    let mut query = query.write();
+                    query.terms = search.terms;
+                    query.order = search.order;
+                    query.seed = crate::platform::random_seed();
+                    query.resume = None;
+                    query.pool = None;
+                    query.saved = Some(search.id);
+                    query.filters = search.filters;
+                    query.active = true;
+                    query.message = None;
+                }
+            }
+            pool::Picker {
+                yiff,
+                onpick: move |pool| {
+                    let mut query = query.write();
+                    query.terms = Vec::new();
+                    query.resume = None;
+                    query.pool = Some(pool);
+                    query.saved = None;
+                    query.filters = None;
                     query.active = true;
                     query.message = None;
                 }
             }
+            if let Some(label) = resume_label {
+                button {
+                    r#type: "button",
+                    class: "resume",
+                    onclick: move |_| {
+                        let saved = match resume.clone() {
+                            Some(s) => s,
+                            None => return,
+                        };
+                        let mut query = query.write();
+                        query.terms = saved.terms;
+                        query.order = saved.order;
+                        query.seed = saved.seed;
+                        query.resume = Some(saved.snapshot);
+                        query.pool = saved.pool;
+                        query.saved = None;
+                        query.filters = saved.filters;
+                        query.active = true;
+                        query.message = None;
+                    },
+                    "{label}"
+                }
+            }
             style { "{options_style}" }
             if let Some(ref msg) = q.message {
                 div { class: "message", "{ msg }" }
             }
             form { class: "options", action: "#", prevent_default: "onsubmit",
+                fieldset {
+                    legend { "Whose profile is this?" }
+                    profile::Switcher {}
+                }
                 fieldset {
                     legend { "In which hand is your phone?" }
                     label {
@@ -254,25 +971,124 @@ pub(crate) fn app() -> Element {
                         "Right"
                     }
                 }
+                fieldset {
+                    legend { "How should pools be shown?" }
+                    label {
+                        input {
+                            r#type: "radio",
+                            name: "layout",
+                            value: "swipe",
+                            oninput: set_layout,
+                            checked: *layout_signal.read() == Layout::Swipe
+                        }
+                        "One page at a time"
+                    }
+                    label {
+                        input {
+                            r#type: "radio",
+                            name: "layout",
+                            value: "reader",
+                            oninput: set_layout,
+                            checked: *layout_signal.read() == Layout::Reader
+                        }
+                        "Comic reader"
+                    }
+                }
+                fieldset { class: "filters",
+                    legend { "Which posts should searches show?" }
+                    filters::Form {
+                        filters: filters_signal(),
+                        onchange: move |f: Filters| {
+                            filters_signal.set(f.clone());
+                            spawn_forever(f.save());
+                        }
+                    }
+                }
+                fieldset { class: "keys",
+                    legend { "Which keys do what?" }
+                    keymap::Form {
+                        keymap: keymap_signal(),
+                        onchange: move |k: Keymap| {
+                            keymap_signal.set(k.clone());
+                            spawn_forever(k.save());
+                        }
+                    }
+                }
+                fieldset { class: "blacklist",
+                    legend { "Which posts should never be shown?" }
+                    textarea {
+                        rows: "4",
+                        placeholder: "one rule per line, like: rating:e -wolf",
+                        value: "{blacklist_signal.read().0}",
+                        oninput: move |evt| blacklist_signal.write().0 = evt.value(),
+                        onchange: move |_| spawn_forever(blacklist_signal.peek().clone().save())
+                    }
+                    button {
+                        r#type: "button",
+                        tabindex: "-1",
+                        onclick: move |_| {
+                            spawn(async move {
+                                let yiff = yiff.peek().clone();
+                                if let Some(rules) = yiff.blacklist().await.record_err() {
+                                    let rules = BlacklistRules(rules);
+                                    *blacklist_signal.write() = rules.clone();
+                                    rules.save().await;
+                                }
+                            });
+                        },
+                        "Import from account"
+                    }
+                }
+                fieldset {
+                    legend { "Which site are you browsing?" }
+                    crate::app::InstancePicker {
+                        instance: credentials_signal.read().instance.clone(),
+                        onchange: move |instance| {
+                            spawn(async move {
+                                let passphrase = credentials_signal.peek().passphrase.clone();
+                                let creds = Credentials::load_for(instance, passphrase).await;
+                                *credentials_signal.write() = creds.clone();
+                                spawn_forever(creds.save());
+                            });
+                        }
+                    }
+                }
+                fieldset {
+                    legend { "Protect your API key with a passphrase?" }
+                    crate::app::PassphraseForm { credentials: credentials_signal }
+                }
                 fieldset {
                     legend { "Want to disconnect your account?" }
                     button {
                         class: "log-out",
                         tabindex: "-1",
                         onclick: move |_| {
-                            *credentials_signal.write() = Default::default();
-                            spawn_forever(Credentials::default().save());
+                            let creds = credentials_signal.peek().logged_out();
+                            *credentials_signal.write() = creds.clone();
+                            spawn_forever(creds.save());
                         },
                         "Log Out"
                     }
                 }
 
+                outbox::Failed {}
+
                 { error_list }
             }
             crate::app::notice {}
         },
-        _ => rsx! {
-            crate::app::viewer { yiff, credentials: credentials_signal, query, hand: hand_signal }
+        q => rsx! {
+            crate::app::viewer {
+                key: "{q.restarts}",
+                yiff,
+                credentials: credentials_signal,
+                query,
+                hand: hand_signal,
+                layout: layout_signal,
+                blacklist: blacklist_signal,
+                filters: filters_signal,
+                keymap: keymap_signal
+            }
         },
     }
 }
@@ -317,9 +1133,149 @@ fn notice() -> Element {
     }
 }
 
+#[component]
+fn InstancePicker(instance: Instance, onchange: EventHandler<Instance>) -> Element {
+    let custom = match &instance {
+        Instance::Custom(url) => Some(url.clone()),
+        _ => None,
+    };
+
+    rsx! {
+        select {
+            class: "instance",
+            value: "{instance.id()}",
+            onchange: move |evt| onchange.call(Instance::from_id(&evt.value())),
+            for preset in Instance::PRESETS {
+                option { value: "{preset.id()}", selected: preset == instance, "{preset.name()}" }
+            }
+            option { value: "custom", selected: custom.is_some(), "Other..." }
+        }
+        if let Some(url) = custom {
+            input {
+                "type": "url",
+                class: "instance-url",
+                placeholder: "https://",
+                value: "{url}",
+                onchange: move |evt| onchange.call(Instance::Custom(evt.value().trim().to_owned()))
+            }
+        }
+    }
+}
+
+#[component]
+fn unlock(credentials: Signal<Credentials>) -> Element {
+    let mut passphrase = use_signal(String::new);
+    let mut problem = use_signal(|| Option::<String>::None);
+    let login_style = include_str!("login.css");
+
+    rsx! {
+        style { "{login_style}" }
+
+        div { style: "display: inline-block; width: min-content;",
+
+            form {
+                prevent_default: "onsubmit",
+                onsubmit: move |_| {
+                    let mut creds = credentials.peek().clone();
+                    match creds.unlock(passphrase.peek().clone()) {
+                        Ok(()) => *credentials.write() = creds,
+                        Err(e) => *problem.write() = Some(e.to_string()),
+                    }
+                },
+
+                label {
+                    "Passphrase:"
+                    input {
+                        "type": "password",
+                        "autofocus": "true",
+                        value: "{passphrase}",
+                        oninput: move |evt| passphrase.set(evt.value())
+                    }
+                }
+
+                button { "type": "submit", "Unlock" }
+
+                if let Some(problem) = problem() {
+                    div { class: "problem", "{problem}" }
+                }
+            }
+
+            div { class: "help",
+                "Forgot it? "
+                button {
+                    r#type: "button",
+                    onclick: move |_| {
+                        let creds = credentials.peek().logged_out();
+                        *credentials.write() = creds;
+                    },
+                    "Log in again"
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn PassphraseForm(credentials: Signal<Credentials>) -> Element {
+    let mut passphrase = use_signal(String::new);
+    let mut saved = use_signal(|| false);
+    let protected = credentials.read().passphrase.is_some();
+
+    rsx! {
+        input {
+            "type": "password",
+            placeholder: if protected { "new passphrase" } else { "passphrase" },
+            value: "{passphrase}",
+            oninput: move |evt| {
+                saved.set(false);
+                passphrase.set(evt.value());
+            }
+        }
+        button {
+            r#type: "button",
+            tabindex: "-1",
+            disabled: passphrase.read().is_empty(),
+            onclick: move |_| {
+                let new = passphrase.peek().clone();
+                spawn(async move {
+                    let mut creds = credentials.peek().clone();
+                    creds.change_passphrase(Some(new)).await;
+                    *credentials.write() = creds;
+                    passphrase.set(String::new());
+                    saved.set(true);
+                });
+            },
+            if protected { "Change" } else { "Set" }
+        }
+        if protected {
+            button {
+                r#type: "button",
+                tabindex: "-1",
+                onclick: move |_| {
+                    spawn(async move {
+                        let mut creds = credentials.peek().clone();
+                        creds.change_passphrase(None).await;
+                        *credentials.write() = creds;
+                        saved.set(true);
+                    });
+                },
+                "Remove"
+            }
+        }
+        if saved() {
+            span { "Saved." }
+        }
+    }
+}
+
 #[component]
 fn login(credentials: Signal<Credentials>) -> Element {
+    let mut checking = use_signal(|| false);
     let login_style = include_str!("login.css");
+    let instance = credentials.read().instance.clone();
+    let site = instance.name().to_owned();
+    let valid = instance.is_valid();
+
     rsx! {
         style { "{login_style}" }
 
@@ -328,11 +1284,50 @@ fn login(credentials: Signal<Credentials>) -> Element {
             form {
                 prevent_default: "onsubmit",
                 onsubmit: move |_| {
-                    let mut creds = credentials.write();
-                    creds.active = true;
-                    spawn_forever(creds.clone().save());
+                    let mut creds = credentials.peek().clone();
+                    if !creds.instance.is_valid() || *checking.peek() {
+                        return;
+                    }
+
+                    if creds.username.trim().is_empty() {
+                        *REJECTED.write() = Some("What's your username?".to_owned());
+                        return;
+                    }
+
+                    checking.set(true);
+                    spawn(async move {
+                        let result = creds.yiff().check_login().await;
+                        checking.set(false);
+
+                        if let Err(e) = result {
+                            *REJECTED.write() = Some(creds.problem(&e));
+                            return;
+                        }
+
+                        *REJECTED.write() = None;
+                        creds.active = true;
+                        *credentials.write() = creds.clone();
+                        creds.save().await;
+                    });
                 },
 
+                label {
+                    "Site:"
+                    crate::app::InstancePicker {
+                        instance,
+                        onchange: move |instance: Instance| {
+                            spawn(async move {
+                                let passphrase = credentials.peek().passphrase.clone();
+                                let mut creds = Credentials::load_for(instance.clone(), passphrase)
+                                    .await;
+                                creds.instance = instance;
+                                creds.active = false;
+                                *credentials.write() = creds;
+                            });
+                        }
+                    }
+                }
+
                 label {
                     "Username:"
                     input {
@@ -356,7 +1351,11 @@ fn login(credentials: Signal<Credentials>) -> Element {
                     }
                 }
 
-                button { "type": "submit", "Log In" }
+                button { "type": "submit", disabled: !valid || checking(), "Log In" }
+
+                if let Some(problem) = REJECTED.read().clone() {
+                    div { class: "problem", "{problem}" }
+                }
             }
 
             div { class: "help",
@@ -365,7 +1364,7 @@ fn login(credentials: Signal<Credentials>) -> Element {
                 strong { "not" }
                 " your password. You can find your API Key under "
                 strong { "Account > Manage API Access" }
-                " once logged into e621."
+                " once logged into {site}."
             }
         }
     }
@@ -377,31 +1376,79 @@ struct Preload {
     kind: String,
 }
 
+impl Preload {
+    fn new(post: &Post) -> Option<Self> {
+        let kind = match post.file.ext {
+            PostFileExtension::Swf => "embed",
+            PostFileExtension::WebM => "video",
+            _ => "image",
+        };
+
+        Some(Preload {
+            href: post.file.url.clone()?,
+            kind: kind.into(),
+        })
+    }
+}
+
 #[component]
 fn viewer(
     yiff: ReadOnlySignal<Yiff>,
     credentials: Signal<Credentials>,
     query: Signal<ActiveQuery>,
     hand: Signal<Hand>,
+    layout: Signal<Layout>,
+    blacklist: Signal<BlacklistRules>,
+    filters: Signal<Filters>,
+    keymap: Signal<Keymap>,
 ) -> Element {
+    let mut sets = use_signal(Vec::<PostSet>::new);
+    use_future(move || async move {
+        let yiff = yiff.peek().clone();
+        if let Some(found) = yiff.post_sets().await.record_err() {
+            sets.set(found);
+        }
+    });
+
     let search = use_resource(move || async move {
         let creds = credentials.read();
         let query_ref = query.read();
-        // let yiff = Yiff::new("https://e926.net", "pkk@tabby.rocks");
+        let yiff = yiff.read().clone().with_blacklist(blacklist.read().parse());
+
+        // Pages of a pool all come up, in order, whether or not they've been
+        // voted on already.
+        if let Some(pool) = &query_ref.pool {
+            let snapshot = query_ref.resume.clone().unwrap_or_default();
+            return Search::read(yiff, pool.clone(), snapshot);
+        }
 
-        let faved = format!("-favoritedby:{}", creds.username);
+        // Entries are checked as they're typed, so this only fails for terms
+        // from somewhere else, like a search saved before they were checked.
+        // Dropping them would search everything, so they're sent as they are.
+        let user_terms = match parse_all(&query_ref.terms) {
+            Ok(q) => q.terms(),
+            Err(e) => {
+                Err::<(), _>(e).record_err();
+                query_ref.terms.clone()
+            }
+        };
+        let mut query_terms: Vec<_> = user_terms.iter().map(String::as_str).collect();
 
-        // TODO: Verify that this is how I should be splitting query terms.
-        let mut query_terms: Vec<_> = query_ref.terms.iter().map(String::as_str).collect();
+        let seed = format!("randseed:{}", query_ref.seed);
+        if query_ref.order == Order::Random {
+            query_terms.push(&seed);
+        }
 
-        query_terms.push("order:random");
-        query_terms.push("score:>=0");
-        query_terms.push("-voted:anything");
-        query_terms.push("-type:swf");
-        query_terms.push(&faved);
+        let filter_terms = match &query_ref.filters {
+            Some(f) => f.terms(&creds.username),
+            None => filters.read().terms(&creds.username),
+        };
+        query_terms.extend(filter_terms.iter().map(String::as_str));
 
-        let yiff = yiff.read().clone();
-        Search::new(yiff, query_terms.as_slice())
+        match &query_ref.resume {
+            Some(snapshot) => Search::resume(yiff, query_terms, query_ref.order, snapshot.clone()),
+            None => Search::new(yiff, query_terms, query_ref.order),
+        }
     });
 
     let search = match &*search.read_unchecked() {
@@ -411,7 +1458,57 @@ fn viewer(
 
     let mut disabled = use_signal(|| false);
     let mut current = use_signal(|| Option::<Arc<Post>>::None);
-    let mut preload = use_signal(Preload::default);
+    let mut choice = use_signal(|| Option::<Choice>::None);
+    let mut preload = use_signal(Vec::<Preload>::new);
+    let mut duplicates = use_signal(|| 0usize);
+    let mut blacklisted = use_signal(|| 0usize);
+    let mut page = use_signal(|| 0usize);
+    let mut show_details = use_signal(|| false);
+    let mut show_help = use_signal(|| false);
+
+    // Leaving the viewer saves whatever session is still waiting.
+    let unsaved = use_signal(|| Rc::new(RefCell::new(Option::<Unsaved>::None)));
+    use_drop({
+        let pending = unsaved.peek().clone();
+        move || {
+            if let Some(unsaved) = pending.borrow_mut().take() {
+                spawn_forever(unsaved.save());
+            }
+        }
+    });
+
+    let mut save_session = move |mut snapshot: Snapshot| {
+        page.set(snapshot.index);
+
+        let query = query.peek();
+        let run = query.saved.map(|id| (id, snapshot.seen.len()));
+        snapshot.truncate(SESSION_LIMIT);
+
+        let session = Session {
+            terms: query.terms.clone(),
+            seed: query.seed,
+            snapshot,
+            pool: query.pool.clone(),
+            filters: query.filters.clone(),
+            order: query.order,
+        };
+
+        let pending = unsaved.peek().clone();
+        let waiting = pending
+            .borrow_mut()
+            .replace(Unsaved { session, run })
+            .is_some();
+
+        if !waiting {
+            spawn_forever(async move {
+                timers::cancelable(SESSION_SAVE_DELAY).1.await.ok();
+                let next = pending.borrow_mut().take();
+                if let Some(unsaved) = next {
+                    unsaved.save().await;
+                }
+            });
+        }
+    };
 
     let search_clone = search.search.clone();
     let mut advance = use_future(move || {
@@ -419,40 +1516,41 @@ fn viewer(
         async move {
             let mut guard = search_clone.lock().await;
             let reply = guard.next().await;
-
-            let post = reply.record_err().flatten();
-            let has_post = post.is_some();
-
-            *current.write() = post;
             *disabled.write() = false;
 
-            if !has_post {
-                let mut write = query.write();
-                write.active = false;
-                write.message = Some("No more posts loaded.".to_owned());
-                return;
-            }
-
-            if let Ok(Some(p)) = guard.peek().await {
-                if let Some(href) = &p.file.url {
-                    let kind = match p.file.ext {
-                        PostFileExtension::Swf => "embed",
-                        PostFileExtension::WebM => "video",
-                        _ => "image",
-                    };
-                    *preload.write() = Preload {
-                        href: href.into(),
-                        kind: kind.into(),
-                    };
+            // Only running out of posts ends the session. After an error the
+            // search stays where it was, and can be tried again.
+            let seen = match reply {
+                Ok(Some(seen)) => seen,
+                Ok(None) => {
+                    unsaved.peek().borrow_mut().take();
+                    spawn_forever(Session::clear());
+                    let mut write = query.write();
+                    write.active = false;
+                    write.message = Some("No more posts loaded.".to_owned());
+                    return;
                 }
-            }
+                Err(e) => {
+                    Err::<(), _>(e).record_err();
+                    return;
+                }
+            };
+
+            *current.write() = Some(seen.post);
+            *choice.write() = seen.choice;
+            save_session(guard.snapshot());
+
+            *duplicates.write() = guard.duplicates();
+            *blacklisted.write() = guard.blacklisted();
+            *preload.write() = guard
+                .upcoming()
+                .iter()
+                .take(PRELOAD)
+                .filter_map(|p| Preload::new(p))
+                .collect();
         }
     });
 
-    let fav_search_clone = search.clone();
-    let like_search_clone = search.clone();
-    let dislike_search_clone = search.clone();
-
     let viewer = match current.as_ref().map(|c| c.file.ext) {
         Some(PostFileExtension::WebM) => rsx! {
             video {
@@ -486,56 +1584,86 @@ fn viewer(
         },
     };
 
-    let fav = Rc::new(RefCell::new(move || {
-        disabled.set(true);
-        if let Some(post) = &*current.read() {
-            let post_id = post.id;
-            let clone = fav_search_clone.clone();
-            let clone2 = fav_search_clone.clone();
-            spawn_forever(async move {
-                clone.yiff.vote_up(post_id).await.record_err();
-            });
-            spawn_forever(async move {
-                clone2.yiff.favorite(post_id).await.record_err();
-            });
+    let queue = move |post_id: u64, actions: &[outbox::Action]| {
+        let creds = credentials.read();
+        for action in actions {
+            outbox::push(creds.instance.base_url(), post_id, *action);
         }
-        advance.restart()
-    }));
+    };
 
-    let fav_clone = fav.clone();
+    // Adds the current post to the set in `slot`, or takes it out again.
+    let mut toggle_set = move |slot: usize| {
+        let post_id = match &*current.peek() {
+            Some(p) => p.id,
+            None => return,
+        };
 
-    let like = Rc::new(RefCell::new(move || {
-        disabled.set(true);
-        if let Some(post) = &*current.read() {
-            let post_id = post.id;
-            let clone = like_search_clone.clone();
-            spawn_forever(async move {
-                clone.yiff.vote_up(post_id).await.record_err();
-            });
-        }
-        advance.restart()
-    }));
+        let mut sets = sets.write();
+        let set = match sets.get_mut(slot) {
+            Some(s) => s,
+            None => return,
+        };
 
-    let like_clone = like.clone();
+        let action = match set.post_ids.iter().position(|p| *p == post_id) {
+            Some(position) => {
+                set.post_ids.remove(position);
+                outbox::Action::RemoveFromSet(set.id)
+            }
+            None => {
+                set.post_ids.push(post_id);
+                outbox::Action::AddToSet(set.id)
+            }
+        };
+        queue(post_id, &[action]);
+    };
 
-    let dislike = Rc::new(RefCell::new(move || {
+    let search_clone = search.clone();
+    let choose = Rc::new(RefCell::new(move |next: Choice| {
         disabled.set(true);
         if let Some(post) = &*current.read() {
+            let previous = *choice.peek();
+            queue(
+                post.id,
+                &outbox::corrections(previous, next, post.is_favorited),
+            );
+
             let post_id = post.id;
-            let clone = dislike_search_clone.clone();
-            if post.is_favorited {
-                spawn_forever(async move {
-                    clone.yiff.unfavorite(post_id).await.record_err();
-                });
-            }
-            let clone = dislike_search_clone.clone();
-            spawn_forever(async move {
-                clone.yiff.vote_down(post_id).await.record_err();
+            let search_clone = search_clone.clone();
+            spawn(async move {
+                let mut guard = search_clone.search.lock().await;
+                guard.record(post_id, next).await.record_err();
+                save_session(guard.snapshot());
             });
         }
         advance.restart()
     }));
-    let dislike_clone = dislike.clone();
+
+    let choose_clone = choose.clone();
+    let fav = choose.clone();
+    let like = choose.clone();
+    let dislike = choose;
+
+    // Swaps `term` into the search, picking up from the current post.
+    let search_clone = search.clone();
+    let refine = move |term: String| {
+        let search_clone = search_clone.clone();
+        spawn(async move {
+            let snapshot = search_clone.search.lock().await.snapshot();
+
+            // Asking for a tag that's excluded (or the other way around)
+            // replaces it.
+            let opposite = match term.strip_prefix('-') {
+                Some(t) => t.to_owned(),
+                None => format!("-{term}"),
+            };
+
+            let mut query = query.write();
+            query.terms.retain(|t| *t != term && *t != opposite);
+            query.terms.push(term);
+            query.resume = Some(snapshot);
+            query.restarts += 1;
+        });
+    };
 
     let rewind = Rc::new(RefCell::new(move || {
         disabled.set(true);
@@ -550,31 +1678,47 @@ fn viewer(
                 reply = guard.next().await;
             }
 
-            let post = match &reply {
-                Err(_) => None,   // TODO
-                Ok(None) => None, // TODO
-                Ok(Some(p)) => Some(p),
+            // On an error the search stays where it was, so keep showing the
+            // same post.
+            *disabled.write() = false;
+            let seen = match reply.record_err().flatten() {
+                Some(s) => s,
+                None => return,
             };
 
-            *current.write() = post.cloned();
-            *disabled.write() = false;
+            *current.write() = Some(seen.post);
+            *choice.write() = seen.choice;
+
+            save_session(guard.snapshot());
         });
     }));
     let rewind_clone = rewind.clone();
 
+    // Turning the page of a pool, without voting on it.
+    let mut skip = move || {
+        disabled.set(true);
+        advance.restart();
+    };
+
+    let pages = query.read().pool.as_ref().map(|p| p.post_ids.len());
+    let reading = pages.is_some();
+
     let logo_e621 = LOGO_E621.as_str();
 
     let sources_current_read = current.read();
+    let instance = credentials.read().instance.clone();
     let sources = sources_current_read
-                        .iter()
-                        .map(|post| rsx! {
-                            li {
-                                ExternalLink { href: Url::parse(&format!("{}/posts/{}", BASE_URL, post.id)).unwrap(),
-
-                                    img { src: "{logo_e621}", alt: "e621 logo" }
-                                }
-                            }
-                        });
+        .iter()
+        .filter_map(|post| instance.url(&format!("/posts/{}", post.id)))
+        .map(|href| {
+            rsx! {
+                li {
+                    ExternalLink { href,
+                        img { src: "{logo_e621}", alt: "{instance.name()} logo" }
+                    }
+                }
+            }
+        });
 
     let other_sources = sources_current_read
         .iter()
@@ -590,31 +1734,57 @@ fn viewer(
             Some(result)
         });
 
+    let chosen = |c: Choice| match *choice.read() == Some(c) {
+        true => "chosen",
+        false => "",
+    };
+
+    let current_id = current.read().as_ref().map(|p| p.id);
+    let in_set = |set: &PostSet| current_id.map_or(false, |id| set.post_ids.contains(&id));
+
+    let previously = choice.read().map(|c| match c {
+        Choice::Favorite => "You favorited this post.",
+        Choice::Like => "You liked this post.",
+        Choice::Dislike => "You disliked this post.",
+    });
+
+    let hint = |action: Action| keymap.read().hint(action);
+
     let viewer_style = include_str!("viewer.css");
     let hand_class = match *hand.read() {
         Hand::Left => "left",
         Hand::Right => "right",
     };
+    let layout_class = match (reading, *layout.read()) {
+        (true, Layout::Reader) => "reader",
+        _ => "",
+    };
     rsx! {
         style { "{viewer_style}" }
-        link {
-            rel: "preload",
-            href: "{preload.read().href}",
-            "as": "{preload.read().kind}"
+        for p in preload.read().iter() {
+            link { key: "{p.href}", rel: "preload", href: "{p.href}", "as": "{p.kind}" }
         }
         div {
             prevent_default: "onkeyup",
             onkeyup: move |evt| {
                 evt.stop_propagation();
-                match evt.data.key() {
-                    Key::ArrowRight => like_clone.borrow_mut()(),
-                    Key::ArrowDown => dislike_clone.borrow_mut()(),
-                    Key::ArrowLeft => rewind_clone.borrow_mut()(),
-                    Key::ArrowUp => fav_clone.borrow_mut()(),
-                    _ => {}
+                let action = keymap.peek().action(&evt.data);
+                match action {
+                    Some(Action::Like) if reading => skip(),
+                    Some(Action::Like) => choose_clone.borrow_mut()(Choice::Like),
+                    Some(Action::Dislike) => choose_clone.borrow_mut()(Choice::Dislike),
+                    Some(Action::Rewind) => rewind_clone.borrow_mut()(),
+                    Some(Action::Favorite) => choose_clone.borrow_mut()(Choice::Favorite),
+                    Some(Action::Details) => show_details.set(!show_details()),
+                    Some(Action::Help) => show_help.set(!show_help()),
+                    Some(Action::Exit) if show_help() => show_help.set(false),
+                    Some(Action::Exit) => query.write().active = false,
+                    Some(Action::Set(slot)) => toggle_set(slot),
+                    None => {}
                 }
             },
             id: "viewport-wrapper",
+            class: layout_class,
             tabindex: "0",
             "autofocus": "true",
             style: "width: 100%; overflow-x: hidden;",
@@ -623,31 +1793,54 @@ fn viewer(
                 ul {
                     li {
                         button {
-                            onclick: move |_| fav.borrow_mut()(),
+                            class: chosen(Choice::Favorite),
+                            onclick: move |_| fav.borrow_mut()(Choice::Favorite),
                             tabindex: "-1",
                             disabled: "{disabled}",
                             title: "favorite",
-                            div { class: "shortcut", "(⬆)" }
+                            if let Some(key) = hint(Action::Favorite) {
+                                div { class: "shortcut", "({key})" }
+                            }
                             "❤️"
                         }
                     }
                     li {
                         button {
-                            onclick: move |_| like.borrow_mut()(),
+                            class: chosen(Choice::Like),
+                            onclick: move |_| like.borrow_mut()(Choice::Like),
                             tabindex: "-1",
                             disabled: "{disabled}",
                             title: "like",
-                            div { class: "shortcut", "(➡)" }
+                            if let Some(key) = hint(Action::Like).filter(|_| !reading) {
+                                div { class: "shortcut", "({key})" }
+                            }
                             "👍"
                         }
                     }
+                    if reading {
+                        li {
+                            button {
+                                onclick: move |_| skip(),
+                                tabindex: "-1",
+                                disabled: "{disabled}",
+                                title: "next page",
+                                if let Some(key) = hint(Action::Like) {
+                                    div { class: "shortcut", "({key})" }
+                                }
+                                "▶️"
+                            }
+                        }
+                    }
                     li {
                         button {
-                            onclick: move |_| dislike.borrow_mut()(),
+                            class: chosen(Choice::Dislike),
+                            onclick: move |_| dislike.borrow_mut()(Choice::Dislike),
                             tabindex: "-1",
                             title: "dislike",
                             disabled: "{disabled}",
-                            div { class: "shortcut", "(⬇)" }
+                            if let Some(key) = hint(Action::Dislike) {
+                                div { class: "shortcut", "({key})" }
+                            }
                             "👎"
                         }
                     }
@@ -657,7 +1850,9 @@ fn viewer(
                             tabindex: "-1",
                             title: "rewind",
                             disabled: "{disabled}",
-                            div { class: "shortcut", "(⬅)" }
+                            if let Some(key) = hint(Action::Rewind) {
+                                div { class: "shortcut", "({key})" }
+                            }
                             "◀️"
                         }
                     }
@@ -669,15 +1864,97 @@ fn viewer(
                     li {
                         button {
                             tabindex: "-1",
+                            title: "back to search",
                             onclick: move |_| query.write().active = false,
+                            if let Some(key) = hint(Action::Exit) {
+                                div { class: "shortcut", "({key})" }
+                            }
                             "❌"
                         }
                     }
+                    li {
+                        button {
+                            class: if show_details() { "chosen" } else { "" },
+                            tabindex: "-1",
+                            title: "details",
+                            onclick: move |_| show_details.set(!show_details()),
+                            if let Some(key) = hint(Action::Details) {
+                                div { class: "shortcut", "({key})" }
+                            }
+                            "ℹ️"
+                        }
+                    }
+                    li {
+                        button {
+                            class: if show_help() { "chosen" } else { "" },
+                            tabindex: "-1",
+                            title: "keys",
+                            onclick: move |_| show_help.set(!show_help()),
+                            if let Some(key) = hint(Action::Help) {
+                                div { class: "shortcut", "({key})" }
+                            }
+                            "⌨️"
+                        }
+                    }
+                }
+            }
+
+            if show_details() {
+                crate::app::PostDetails {
+                    post: current,
+                    instance: instance.clone(),
+                    hand,
+                    onrefine: match reading {
+                        true => None,
+                        false => Some(EventHandler::new(refine.clone())),
+                    }
+                }
+            }
+
+            if show_help() {
+                keymap::Cheatsheet { keymap: keymap(), onclose: move |_| show_help.set(false) }
+            }
+
+            if !sets.read().is_empty() {
+                nav { class: "set-nav",
+                    ul {
+                        for (slot, set) in sets.read().iter().take(9).enumerate() {
+                            li { key: "{set.id}",
+                                button {
+                                    class: if in_set(set) { "chosen" } else { "" },
+                                    onclick: move |_| toggle_set(slot),
+                                    tabindex: "-1",
+                                    title: "add to or remove from {set.name}",
+                                    if let Some(key) = hint(Action::Set(slot)) {
+                                        div { class: "shortcut", "({key})" }
+                                    }
+                                    "{set.name}"
+                                }
+                            }
+                        }
+                    }
                 }
             }
 
+            if let Some(previously) = previously {
+                div { class: "previous-choice", "{previously}" }
+            }
+
             div { class: "details",
 
+                div { class: "outbox-status", outbox::Status {} }
+
+                if let Some(pages) = pages {
+                    div { class: "page", "page {page} of {pages}" }
+                }
+
+                if blacklisted() > 0 {
+                    div { class: "diagnostics", "blacklisted {blacklisted} posts" }
+                }
+                if duplicates() > 0 {
+                    div { class: "diagnostics", "{duplicates} duplicates skipped" }
+                }
+
                 ul { class: "sources", { sources } }
 
                 ul { class: "other-sources", { other_sources } }
@@ -685,3 +1962,153 @@ fn viewer(
         }
     }
 }
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Everything known about a post, with its tags grouped by category.
+#[component]
+fn PostDetails(
+    post: Signal<Option<Arc<Post>>>,
+    instance: Instance,
+    hand: Signal<Hand>,
+    /// Called with a tag (or `-tag`) to put in the running search.
+    onrefine: Option<EventHandler<String>>,
+) -> Element {
+    let post = post()?;
+    let tags = &post.tags;
+    let groups = [
+        (TagCategory::Artist, &tags.artist),
+        (TagCategory::Copyright, &tags.copyright),
+        (TagCategory::Character, &tags.character),
+        (TagCategory::Species, &tags.species),
+        (TagCategory::General, &tags.general),
+        (TagCategory::Lore, &tags.lore),
+        (TagCategory::Meta, &tags.meta),
+        (TagCategory::Invalid, &tags.invalid),
+    ];
+    let groups = groups.into_iter().filter(|(_, tags)| !tags.is_empty());
+
+    let artists = match tags.artist.is_empty() {
+        true => "unknown artist".to_owned(),
+        false => tags.artist.join(", "),
+    };
+
+    let rating = match post.rating {
+        PostRating::Safe => "safe",
+        PostRating::Questionable => "questionable",
+        PostRating::Explicit => "explicit",
+    };
+
+    let file = &post.file;
+    let size = human_size(file.size);
+    let uploaded = post.created_at.format("%Y-%m-%d");
+    let uploader = instance.url(&format!("/users/{}", post.uploader_id));
+
+    // The panel goes on the side away from the buttons, so it doesn't cover
+    // them.
+    let side = match *hand.read() {
+        Hand::Left => "right",
+        Hand::Right => "left",
+    };
+
+    rsx! {
+        aside { class: "post-details {side}",
+            h2 { class: "artist", "{artists}" }
+
+            dl {
+                dt { "rating" }
+                dd { "{rating}" }
+                dt { "score" }
+                dd { "{post.score.total}" }
+                dt { "favorites" }
+                dd { "{post.fav_count}" }
+                dt { "size" }
+                dd { "{file.width}×{file.height}, {size}" }
+                dt { "uploaded" }
+                dd {
+                    "{uploaded} by "
+                    match uploader {
+                        Some(href) => rsx! {
+                            ExternalLink { href, "user #{post.uploader_id}" }
+                        },
+                        None => rsx! { "user #{post.uploader_id}" },
+                    }
+                }
+            }
+
+            for (category, tags) in groups {
+                section { key: "{category.name()}", class: "tag-group",
+                    h3 { "{category.name()}" }
+                    ul {
+                        for tag in tags.iter() {
+                            li { key: "{tag}", style: "color: {tag::colour(category)};",
+                                "{tag}"
+                                if let Some(onrefine) = onrefine {
+                                    button {
+                                        class: "refine",
+                                        tabindex: "-1",
+                                        title: "add to search",
+                                        onclick: {
+                                            let tag = tag.clone();
+                                            move |_| onrefine.call(tag.clone())
+                                        },
+                                        "+"
+                                    }
+                                    button {
+                                        class: "refine",
+                                        tabindex: "-1",
+                                        title: "exclude from search",
+                                        onclick: {
+                                            let tag = tag.clone();
+                                            move |_| onrefine.call(format!("-{tag}"))
+                                        },
+                                        "−"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_instances_need_https() {
+        let cases = [
+            ("https://example.com", true),
+            ("https://example.com/", true),
+            ("http://example.com", false),
+            ("http://localhost:3000", true),
+            ("http://127.0.0.1:3000", true),
+            ("http://[::1]:3000", true),
+            ("ftp://example.com", false),
+            ("example.com", false),
+            ("", false),
+        ];
+
+        for (url, valid) in cases {
+            assert_eq!(Instance::Custom(url.to_owned()).is_valid(), valid, "{url}");
+        }
+        assert!(Instance::E621.is_valid());
+    }
+}
@@ -0,0 +1,266 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Client-side blacklisting, using the same line-based syntax as e621.
+//!
+//! Each non-empty line is a rule. A post is blacklisted when it matches any
+//! rule, and it matches a rule when:
+//!
+//!  - it has every plain tag (`wolf`),
+//!  - it has none of the negated tags (`-fox`),
+//!  - it has at least one of the `~` tags, if there are any (`~cat ~dog`).
+//!
+//! Metatags like `rating:e`, `score:<0`, `favcount:>=10`, `type:webm` and
+//! `id:123` can be used anywhere a tag can.
+
+use rs621::post::{Post, PostFileExtension, PostRating};
+
+use std::collections::HashSet;
+
+use crate::query::Op;
+use crate::yiff::post_tags;
+
+/// A comparison from a metatag, like the `<0` in `score:<0`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Range {
+    Eq(i64),
+    Lt(i64),
+    Le(i64),
+    Gt(i64),
+    Ge(i64),
+    /// Inclusive on both ends, like `1..5`.
+    Between(i64, i64),
+}
+
+impl Range {
+    /// Parses what comes after the colon, the same way searches do. Open
+    /// ranges (`..5` and `1..`) become [`Range::Le`] and [`Range::Ge`].
+    pub fn parse(text: &str) -> Option<Self> {
+        let (op, value) = Op::split(text.trim());
+
+        let range = match op {
+            Op::Eq => Range::Eq(value.parse().ok()?),
+            Op::Lt => Range::Lt(value.parse().ok()?),
+            Op::Le => Range::Le(value.parse().ok()?),
+            Op::Gt => Range::Gt(value.parse().ok()?),
+            Op::Ge => Range::Ge(value.parse().ok()?),
+            Op::Range => match value.split_once("..")? {
+                ("", high) => Range::Le(high.parse().ok()?),
+                (low, "") => Range::Ge(low.parse().ok()?),
+                (low, high) => Range::Between(low.parse().ok()?, high.parse().ok()?),
+            },
+        };
+
+        Some(range)
+    }
+
+    pub fn contains(self, value: i64) -> bool {
+        match self {
+            Range::Eq(v) => value == v,
+            Range::Lt(v) => value < v,
+            Range::Le(v) => value <= v,
+            Range::Gt(v) => value > v,
+            Range::Ge(v) => value >= v,
+            Range::Between(low, high) => low <= value && value <= high,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Term {
+    Tag(String),
+    Rating(char),
+    Score(Range),
+    FavCount(Range),
+    Id(Range),
+    Type(String),
+}
+
+impl Term {
+    fn parse(text: &str) -> Self {
+        let parsed = match text.split_once(':') {
+            Some(("rating", v)) => v.chars().next().map(Term::Rating),
+            Some(("score", v)) => Range::parse(v).map(Term::Score),
+            Some(("favcount", v)) => Range::parse(v).map(Term::FavCount),
+            Some(("id", v)) => Range::parse(v).map(Term::Id),
+            Some(("type", v)) => Some(Term::Type(v.to_owned())),
+            _ => None,
+        };
+
+        parsed.unwrap_or_else(|| Term::Tag(text.to_owned()))
+    }
+
+    fn matches(&self, post: &Post, tags: &HashSet<&str>) -> bool {
+        match self {
+            Term::Tag(tag) => tags.contains(tag.as_str()),
+            Term::Rating(r) => rating(post) == *r,
+            Term::Score(range) => range.contains(post.score.total.into()),
+            Term::FavCount(range) => range.contains(post.fav_count as i64),
+            Term::Id(range) => range.contains(post.id as i64),
+            Term::Type(ext) => file_type(post).map_or(false, |t| t == ext),
+        }
+    }
+}
+
+fn rating(post: &Post) -> char {
+    match post.rating {
+        PostRating::Safe => 's',
+        PostRating::Questionable => 'q',
+        PostRating::Explicit => 'e',
+    }
+}
+
+fn file_type(post: &Post) -> Option<&str> {
+    match post.file.ext {
+        PostFileExtension::WebM => Some("webm"),
+        PostFileExtension::Swf => Some("swf"),
+        _ => post.file.url.as_deref()?.rsplit('.').next(),
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct Rule {
+    all_of: Vec<Term>,
+    none_of: Vec<Term>,
+    any_of: Vec<Term>,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let mut rule = Rule::default();
+
+        for word in line.split_whitespace() {
+            let word = word.to_lowercase();
+            if let Some(w) = word.strip_prefix('-') {
+                rule.none_of.push(Term::parse(w));
+            } else if let Some(w) = word.strip_prefix('~') {
+                rule.any_of.push(Term::parse(w));
+            } else {
+                rule.all_of.push(Term::parse(&word));
+            }
+        }
+
+        if rule.all_of.is_empty() && rule.any_of.is_empty() {
+            // A rule of only negations would hide nearly everything.
+            return None;
+        }
+
+        Some(rule)
+    }
+
+    fn matches(&self, post: &Post, tags: &HashSet<&str>) -> bool {
+        self.all_of.iter().all(|t| t.matches(post, tags))
+            && !self.none_of.iter().any(|t| t.matches(post, tags))
+            && (self.any_of.is_empty() || self.any_of.iter().any(|t| t.matches(post, tags)))
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Blacklist {
+    rules: Vec<Rule>,
+}
+
+impl Blacklist {
+    /// Parses one rule per line. Lines that can't ever match are ignored.
+    pub fn parse(text: &str) -> Self {
+        Self {
+            rules: text.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    /// Whether any rule matches `post`.
+    pub fn matches(&self, post: &Post) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        let tags: HashSet<&str> = post_tags(post).collect();
+        self.rules.iter().any(|r| r.matches(post, &tags))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSTS: &str = include_str!("assets/fixtures/posts.json");
+
+    /// Ids of the fixture posts `rules` blacklists.
+    fn blocked(rules: &str) -> Vec<u64> {
+        let posts: Vec<Post> = serde_json::from_str(POSTS).unwrap();
+        let blacklist = Blacklist::parse(rules);
+        posts
+            .iter()
+            .filter(|p| blacklist.matches(p))
+            .map(|p| p.id)
+            .collect()
+    }
+
+    #[test]
+    fn matches_posts() {
+        let cases: &[(&str, &[u64])] = &[
+            ("", &[]),
+            ("wolf", &[1000]),
+            ("Wolf", &[1000]),
+            ("solo outside", &[1000, 1002, 1007, 1008, 1010]),
+            ("solo outside -mammal", &[1002]),
+            ("~fox ~otter", &[1001, 1004]),
+            ("outside ~fox ~otter", &[1004]),
+            ("wolf\nfox", &[1000, 1001]),
+            ("rating:e", &[1008, 1011]),
+            ("rating:explicit", &[1008, 1011]),
+            ("score:<0", &[1007]),
+            ("score:>=40", &[1001, 1003]),
+            ("favcount:30..41", &[1001, 1003]),
+            ("id:1005", &[1005]),
+            ("solo -rating:s", &[1008]),
+        ];
+
+        for (rules, expected) in cases {
+            assert_eq!(blocked(rules), *expected, "{rules:?}");
+        }
+    }
+
+    #[test]
+    fn ignores_rules_of_only_negations() {
+        assert_eq!(Blacklist::parse("-wolf"), Blacklist::default());
+        assert_eq!(Blacklist::parse("-wolf -fox\n"), Blacklist::default());
+        assert_eq!(blocked("-wolf\nfox"), [1001]);
+    }
+
+    #[test]
+    fn parse_ranges() {
+        let cases = [
+            ("5", Some(Range::Eq(5))),
+            ("<0", Some(Range::Lt(0))),
+            ("<=-1", Some(Range::Le(-1))),
+            (">10", Some(Range::Gt(10))),
+            (">=10", Some(Range::Ge(10))),
+            ("1..5", Some(Range::Between(1, 5))),
+            ("..5", Some(Range::Le(5))),
+            ("1..", Some(Range::Ge(1))),
+            ("..", None),
+            (">=1..5", None),
+            ("high", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(Range::parse(text), expected, "{text}");
+        }
+    }
+}
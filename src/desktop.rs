@@ -20,6 +20,17 @@ use crate::app::app;
 
 use std::future::Future;
 
+/// Posts on either side of the current one kept in memory while swiping.
+pub(crate) const HISTORY_LIMIT: usize = 100;
+
+pub(crate) type BoxFuture<'a, T> = futures::future::BoxFuture<'a, T>;
+pub(crate) type BoxStream<'a, T> = futures::stream::BoxStream<'a, T>;
+
+/// Bound for values that have to cross into [`spawn`]ed tasks.
+pub(crate) trait MaybeSend: Send + Sync {}
+
+impl<T: Send + Sync> MaybeSend for T {}
+
 pub(crate) fn spawn<F>(fut: F)
 where
     F: Future<Output = ()> + Send + 'static,
@@ -27,6 +38,14 @@ where
     tokio::spawn(fut);
 }
 
+/// A fresh seed for `randseed:`.
+pub(crate) fn random_seed() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    RandomState::new().build_hasher().finish()
+}
+
 pub(super) fn main() {
     dioxus::launch(app);
 }
@@ -0,0 +1,351 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Filters added to every search, unless a saved search has its own.
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{store, ResultExt};
+use crate::profile;
+
+const KEY: &str = "filters";
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filters {
+    pub safe: bool,
+    pub questionable: bool,
+    pub explicit: bool,
+    /// Hide posts scoring less than this.
+    pub min_score: Option<i64>,
+    /// `jpg` and `png`.
+    pub images: bool,
+    /// `gif`.
+    pub animations: bool,
+    /// `webm`.
+    pub videos: bool,
+    /// `swf`, which can't be shown anyway.
+    pub flash: bool,
+    /// Show posts the user already voted on.
+    pub voted: bool,
+    /// Show posts the user already favorited.
+    pub favorited: bool,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self {
+            safe: true,
+            questionable: true,
+            explicit: true,
+            min_score: Some(0),
+            images: true,
+            animations: true,
+            videos: true,
+            flash: false,
+            voted: false,
+            favorited: false,
+        }
+    }
+}
+
+impl Filters {
+    pub async fn load() -> Self {
+        // TODO: Find a spawn_blocking replacement.
+        store().get(&profile::key(KEY)).unwrap_or_default()
+    }
+
+    pub async fn save(self) {
+        // TODO: Find a spawn_blocking replacement.
+        store().set(&profile::key(KEY), &self).record_err();
+    }
+
+    /// Search terms for these filters, as seen by `username`.
+    pub fn terms(&self, username: &str) -> Vec<String> {
+        let mut terms = Vec::new();
+
+        // Turning every rating off would show nothing, so treat it like
+        // turning them all on.
+        let ratings = [
+            (self.safe, "s"),
+            (self.questionable, "q"),
+            (self.explicit, "e"),
+        ];
+        let shown = ratings.iter().filter(|(on, _)| *on).count();
+        match shown {
+            1 => terms.extend(
+                ratings
+                    .iter()
+                    .filter(|(on, _)| *on)
+                    .map(|(_, r)| format!("rating:{r}")),
+            ),
+            2 => terms.extend(
+                ratings
+                    .iter()
+                    .filter(|(on, _)| !*on)
+                    .map(|(_, r)| format!("-rating:{r}")),
+            ),
+            _ => (),
+        }
+
+        if let Some(score) = self.min_score {
+            terms.push(format!("score:>={score}"));
+        }
+
+        let types = [
+            (self.images, "jpg"),
+            (self.images, "png"),
+            (self.animations, "gif"),
+            (self.videos, "webm"),
+            (self.flash, "swf"),
+        ];
+        if types.iter().any(|(on, _)| *on) {
+            terms.extend(
+                types
+                    .iter()
+                    .filter(|(on, _)| !*on)
+                    .map(|(_, t)| format!("-type:{t}")),
+            );
+        }
+
+        if !self.voted {
+            terms.push("-voted:anything".to_owned());
+        }
+
+        if !self.favorited {
+            terms.push(format!("-favoritedby:{username}"));
+        }
+
+        terms
+    }
+}
+
+#[component]
+pub fn Form(filters: Filters, onchange: EventHandler<Filters>) -> Element {
+    let toggle = |field: fn(&mut Filters) -> &mut bool| {
+        let filters = filters.clone();
+        move |_| {
+            let mut next = filters.clone();
+            let value = field(&mut next);
+            *value = !*value;
+            onchange.call(next);
+        }
+    };
+
+    let score = filters.min_score.map(|s| s.to_string()).unwrap_or_default();
+    let set_score = {
+        let filters = filters.clone();
+        move |evt: Event<FormData>| {
+            let mut next = filters.clone();
+            next.min_score = evt.value().trim().parse().ok();
+            onchange.call(next);
+        }
+    };
+
+    rsx! {
+        div { class: "filters",
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.safe,
+                    oninput: toggle(|f| &mut f.safe)
+                }
+                "Safe"
+            }
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.questionable,
+                    oninput: toggle(|f| &mut f.questionable)
+                }
+                "Questionable"
+            }
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.explicit,
+                    oninput: toggle(|f| &mut f.explicit)
+                }
+                "Explicit"
+            }
+        }
+        div { class: "filters",
+            label {
+                "Minimum score: "
+                input {
+                    r#type: "number",
+                    placeholder: "any",
+                    value: "{score}",
+                    onchange: set_score
+                }
+            }
+        }
+        div { class: "filters",
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.images,
+                    oninput: toggle(|f| &mut f.images)
+                }
+                "Images"
+            }
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.animations,
+                    oninput: toggle(|f| &mut f.animations)
+                }
+                "Animations"
+            }
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.videos,
+                    oninput: toggle(|f| &mut f.videos)
+                }
+                "Videos"
+            }
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.flash,
+                    oninput: toggle(|f| &mut f.flash)
+                }
+                "Flash"
+            }
+        }
+        div { class: "filters",
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.voted,
+                    oninput: toggle(|f| &mut f.voted)
+                }
+                "Posts I voted on"
+            }
+            label {
+                input {
+                    r#type: "checkbox",
+                    checked: filters.favorited,
+                    oninput: toggle(|f| &mut f.favorited)
+                }
+                "Posts I favorited"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Filters that add no terms at all.
+    fn everything() -> Filters {
+        Filters {
+            min_score: None,
+            flash: true,
+            voted: true,
+            favorited: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            Filters::default().terms("someone"),
+            [
+                "score:>=0",
+                "-type:swf",
+                "-voted:anything",
+                "-favoritedby:someone",
+            ],
+        );
+        assert!(everything().terms("someone").is_empty());
+    }
+
+    #[test]
+    fn ratings_collapse_to_one_term() {
+        let cases: &[((bool, bool, bool), &[&str])] = &[
+            ((true, true, true), &[]),
+            ((false, false, false), &[]),
+            ((true, false, false), &["rating:s"]),
+            ((false, true, false), &["rating:q"]),
+            ((false, false, true), &["rating:e"]),
+            ((true, true, false), &["-rating:e"]),
+            ((true, false, true), &["-rating:q"]),
+            ((false, true, true), &["-rating:s"]),
+        ];
+
+        for &((safe, questionable, explicit), expected) in cases {
+            let filters = Filters {
+                safe,
+                questionable,
+                explicit,
+                ..everything()
+            };
+            assert_eq!(
+                filters.terms("someone"),
+                expected,
+                "s: {safe}, q: {questionable}, e: {explicit}",
+            );
+        }
+    }
+
+    #[test]
+    fn file_types() {
+        let filters = Filters {
+            images: false,
+            videos: false,
+            ..everything()
+        };
+        assert_eq!(
+            filters.terms("someone"),
+            ["-type:jpg", "-type:png", "-type:webm"]
+        );
+
+        // Like ratings, hiding every type would show nothing.
+        let filters = Filters {
+            images: false,
+            animations: false,
+            videos: false,
+            flash: false,
+            ..everything()
+        };
+        assert!(filters.terms("someone").is_empty());
+    }
+
+    #[test]
+    fn favorites_are_the_users_own() {
+        let filters = Filters {
+            favorited: false,
+            ..everything()
+        };
+        assert_eq!(filters.terms("some_one"), ["-favoritedby:some_one"]);
+    }
+
+    #[test]
+    fn min_score() {
+        let filters = Filters {
+            min_score: Some(-5),
+            ..everything()
+        };
+        assert_eq!(filters.terms("someone"), ["score:>=-5"]);
+    }
+}
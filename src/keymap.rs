@@ -0,0 +1,450 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Which keys do what in the viewer.
+
+use dioxus::prelude::*;
+use keyboard_types::{Key, Location};
+use serde::{Deserialize, Serialize};
+
+use crate::app::{store, ResultExt};
+use crate::profile;
+
+const KEY: &str = "keymap";
+
+/// Something a key can do in the viewer.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Favorite,
+    /// Or go to the next page, when reading a pool.
+    Like,
+    Dislike,
+    Rewind,
+    Details,
+    Help,
+    Exit,
+    /// Adds to or removes from the post set in this slot, counting from zero.
+    Set(usize),
+}
+
+impl Action {
+    pub const ALL: [Action; 16] = [
+        Action::Favorite,
+        Action::Like,
+        Action::Dislike,
+        Action::Rewind,
+        Action::Details,
+        Action::Help,
+        Action::Exit,
+        Action::Set(0),
+        Action::Set(1),
+        Action::Set(2),
+        Action::Set(3),
+        Action::Set(4),
+        Action::Set(5),
+        Action::Set(6),
+        Action::Set(7),
+        Action::Set(8),
+    ];
+
+    pub fn name(self) -> String {
+        match self {
+            Action::Favorite => "Favorite".to_owned(),
+            Action::Like => "Like / next page".to_owned(),
+            Action::Dislike => "Dislike".to_owned(),
+            Action::Rewind => "Rewind".to_owned(),
+            Action::Details => "Show details".to_owned(),
+            Action::Help => "Show these keys".to_owned(),
+            Action::Exit => "Back to search".to_owned(),
+            Action::Set(slot) => format!("Post set {}", slot + 1),
+        }
+    }
+}
+
+/// Ready-made bindings for moving through posts.
+pub const PRESETS: [(&str, &[(&str, Action)]); 4] = [
+    (
+        "Arrows",
+        &[
+            ("ArrowUp", Action::Favorite),
+            ("ArrowRight", Action::Like),
+            ("ArrowDown", Action::Dislike),
+            ("ArrowLeft", Action::Rewind),
+        ],
+    ),
+    (
+        "WASD",
+        &[
+            ("w", Action::Favorite),
+            ("d", Action::Like),
+            ("s", Action::Dislike),
+            ("a", Action::Rewind),
+        ],
+    ),
+    (
+        "Vim",
+        &[
+            ("k", Action::Favorite),
+            ("l", Action::Like),
+            ("j", Action::Dislike),
+            ("h", Action::Rewind),
+        ],
+    ),
+    (
+        "Numpad",
+        &[
+            ("Numpad8", Action::Favorite),
+            ("Numpad6", Action::Like),
+            ("Numpad2", Action::Dislike),
+            ("Numpad4", Action::Rewind),
+            // The same keys, with num lock off.
+            ("NumpadArrowUp", Action::Favorite),
+            ("NumpadArrowRight", Action::Like),
+            ("NumpadArrowDown", Action::Dislike),
+            ("NumpadArrowLeft", Action::Rewind),
+        ],
+    ),
+];
+
+/// Names a key the way [`Keymap`] stores it: `ArrowUp`, `w`, or `Numpad8`
+/// for keys on the number pad, so they can do something different from the
+/// keys above the letters.
+fn key_name(key: &Key, location: Location) -> String {
+    let key = match key {
+        Key::Character(c) => c.to_lowercase(),
+        k => k.to_string(),
+    };
+
+    match location {
+        Location::Numpad => format!("Numpad{key}"),
+        _ => key,
+    }
+}
+
+/// Short label for a key named by [`key_name`].
+pub fn symbol(key: &str) -> String {
+    let (numpad, key) = match key.strip_prefix("Numpad") {
+        Some(k) if !k.is_empty() => (true, k),
+        _ => (false, key),
+    };
+
+    let symbol = match key {
+        "ArrowUp" => "⬆",
+        "ArrowRight" => "➡",
+        "ArrowDown" => "⬇",
+        "ArrowLeft" => "⬅",
+        "Escape" => "esc",
+        " " => "space",
+        k => k,
+    };
+
+    match numpad {
+        true => format!("num {symbol}"),
+        false => symbol.to_owned(),
+    }
+}
+
+/// Which keys do what in the viewer. A key does one thing, but a thing can
+/// have any number of keys.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Keymap {
+    bindings: Vec<(String, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self {
+            bindings: Vec::new(),
+        };
+        keymap.add(PRESETS[0].1);
+        keymap.bind("i".to_owned(), Action::Details);
+        keymap.bind("?".to_owned(), Action::Help);
+        keymap.bind("Escape".to_owned(), Action::Exit);
+        for slot in 0..9 {
+            keymap.bind((slot + 1).to_string(), Action::Set(slot));
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    pub async fn load() -> Self {
+        // TODO: Find a spawn_blocking replacement.
+        store().get(&profile::key(KEY)).unwrap_or_default()
+    }
+
+    pub async fn save(self) {
+        // TODO: Find a spawn_blocking replacement.
+        store().set(&profile::key(KEY), &self).record_err();
+    }
+
+    /// What pressing the key in `data` does, if anything.
+    pub fn action(&self, data: &KeyboardData) -> Option<Action> {
+        let name = key_name(&data.key(), data.location());
+        self.bindings
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, action)| *action)
+    }
+
+    /// Names of the keys that do `action`.
+    pub fn keys(&self, action: Action) -> impl Iterator<Item = &str> {
+        self.bindings
+            .iter()
+            .filter(move |(_, a)| *a == action)
+            .map(|(key, _)| key.as_str())
+    }
+
+    /// Label for the first key that does `action`, for showing on buttons.
+    pub fn hint(&self, action: Action) -> Option<String> {
+        self.keys(action).next().map(symbol)
+    }
+
+    /// Makes `key` do `action`, instead of whatever it did before.
+    pub fn bind(&mut self, key: String, action: Action) {
+        self.unbind(&key);
+        self.bindings.push((key, action));
+    }
+
+    pub fn unbind(&mut self, key: &str) {
+        self.bindings.retain(|(k, _)| k != key);
+    }
+
+    /// Adds a preset's bindings, taking over any keys it uses.
+    pub fn add(&mut self, bindings: &[(&str, Action)]) {
+        for (key, action) in bindings {
+            self.bind((*key).to_owned(), *action);
+        }
+    }
+}
+
+#[component]
+pub fn Form(keymap: Keymap, onchange: EventHandler<Keymap>) -> Element {
+    let unbind = |key: &str| {
+        let keymap = keymap.clone();
+        let key = key.to_owned();
+        move |_| {
+            let mut next = keymap.clone();
+            next.unbind(&key);
+            onchange.call(next);
+        }
+    };
+
+    let bind = |action: Action| {
+        let keymap = keymap.clone();
+        move |evt: Event<KeyboardData>| {
+            // Keep tab for leaving the box, and wait for whatever key a
+            // modifier is held down for.
+            let key = evt.key();
+            if matches!(
+                key,
+                Key::Tab | Key::Shift | Key::Control | Key::Alt | Key::Meta | Key::CapsLock
+            ) {
+                return;
+            }
+
+            let mut next = keymap.clone();
+            next.bind(key_name(&key, evt.location()), action);
+            onchange.call(next);
+        }
+    };
+
+    let add = |bindings: &'static [(&'static str, Action)]| {
+        let keymap = keymap.clone();
+        move |_| {
+            let mut next = keymap.clone();
+            next.add(bindings);
+            onchange.call(next);
+        }
+    };
+
+    rsx! {
+        table { class: "keymap",
+            for action in Action::ALL {
+                tr { key: "{action.name()}",
+                    th { "{action.name()}" }
+                    td {
+                        for key in keymap.keys(action) {
+                            button {
+                                key: "{key}",
+                                r#type: "button",
+                                class: "key",
+                                tabindex: "-1",
+                                title: "stop using this key",
+                                onclick: unbind(key),
+                                "{symbol(key)} ✕"
+                            }
+                        }
+                        input {
+                            r#type: "text",
+                            class: "bind",
+                            readonly: true,
+                            placeholder: "press a key to add",
+                            value: "",
+                            onkeydown: bind(action)
+                        }
+                    }
+                }
+            }
+        }
+        div { class: "keymap-presets",
+            "Add: "
+            for (name, bindings) in PRESETS {
+                button {
+                    key: "{name}",
+                    r#type: "button",
+                    tabindex: "-1",
+                    onclick: add(bindings),
+                    "{name}"
+                }
+            }
+            button {
+                r#type: "button",
+                tabindex: "-1",
+                onclick: move |_| onchange.call(Keymap::default()),
+                "Reset"
+            }
+        }
+    }
+}
+
+/// Every key that does something in the viewer, over the top of it.
+#[component]
+pub fn Cheatsheet(keymap: Keymap, onclose: EventHandler) -> Element {
+    let bound = Action::ALL.into_iter().filter_map(|action| {
+        let keys: Vec<_> = keymap.keys(action).map(symbol).collect();
+        match keys.is_empty() {
+            true => None,
+            false => Some((action, keys.join(", "))),
+        }
+    });
+
+    rsx! {
+        div { class: "cheatsheet", onclick: move |_| onclose.call(()),
+            dl {
+                for (action, keys) in bound {
+                    div { key: "{action.name()}",
+                        dt { "{keys}" }
+                        dd { "{action.name()}" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    fn keymap(bindings: &[(&str, Action)]) -> Keymap {
+        let mut keymap = Keymap {
+            bindings: Vec::new(),
+        };
+        keymap.add(bindings);
+        keymap
+    }
+
+    fn keys(keymap: &Keymap, action: Action) -> Vec<&str> {
+        keymap.keys(action).collect()
+    }
+
+    #[test]
+    fn names_keys() {
+        let cases = [
+            (Key::Character("W".to_owned()), Location::Standard, "w"),
+            (Key::Character("?".to_owned()), Location::Standard, "?"),
+            (Key::ArrowUp, Location::Standard, "ArrowUp"),
+            (Key::Escape, Location::Standard, "Escape"),
+            (Key::Character("8".to_owned()), Location::Standard, "8"),
+            (Key::Character("8".to_owned()), Location::Numpad, "Numpad8"),
+            (Key::ArrowUp, Location::Numpad, "NumpadArrowUp"),
+        ];
+
+        for (key, location, expected) in cases {
+            assert_eq!(key_name(&key, location), expected, "{key:?}");
+        }
+    }
+
+    #[test]
+    fn symbols() {
+        let cases = [
+            ("ArrowUp", "⬆"),
+            ("ArrowLeft", "⬅"),
+            ("Escape", "esc"),
+            (" ", "space"),
+            ("w", "w"),
+            ("Numpad8", "num 8"),
+            ("NumpadArrowDown", "num ⬇"),
+            ("Numpad", "Numpad"),
+        ];
+
+        for (key, expected) in cases {
+            assert_eq!(symbol(key), expected, "{key:?}");
+        }
+    }
+
+    #[test]
+    fn binding_a_taken_key_moves_it() {
+        let mut keymap = keymap(&[("w", Action::Favorite), ("k", Action::Favorite)]);
+        keymap.bind("w".to_owned(), Action::Dislike);
+
+        assert_eq!(keys(&keymap, Action::Favorite), ["k"]);
+        assert_eq!(keys(&keymap, Action::Dislike), ["w"]);
+    }
+
+    #[test]
+    fn unbinding_leaves_other_keys() {
+        let mut keymap = keymap(&[("w", Action::Favorite), ("k", Action::Favorite)]);
+        keymap.unbind("w");
+        keymap.unbind("x");
+
+        assert_eq!(keys(&keymap, Action::Favorite), ["k"]);
+        assert_eq!(keymap.hint(Action::Favorite).as_deref(), Some("k"));
+        assert_eq!(keymap.hint(Action::Like), None);
+    }
+
+    #[test]
+    fn adding_a_preset_takes_over_its_keys() {
+        let mut keymap = keymap(&[("w", Action::Exit), ("q", Action::Exit)]);
+        keymap.add(PRESETS[1].1);
+
+        assert_eq!(keys(&keymap, Action::Exit), ["q"]);
+        assert_eq!(keys(&keymap, Action::Favorite), ["w"]);
+    }
+
+    #[test]
+    fn presets_bind_each_key_once() {
+        for (name, bindings) in PRESETS {
+            let mut seen = HashSet::new();
+            for (key, _) in bindings {
+                assert!(seen.insert(key), "{name} binds {key} twice");
+            }
+        }
+    }
+
+    #[test]
+    fn default_binds_every_action() {
+        let keymap = Keymap::default();
+        for action in Action::ALL {
+            assert!(keymap.hint(action).is_some(), "{action:?} has no key");
+        }
+    }
+}
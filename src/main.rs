@@ -17,6 +17,15 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 mod app;
+mod blacklist;
+mod filters;
+mod keymap;
+mod outbox;
+mod pool;
+mod profile;
+mod query;
+mod saved;
+mod secret;
 mod tag;
 mod timers;
 mod yiff;
@@ -0,0 +1,454 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Votes and favorites waiting to be sent.
+//!
+//! Actions are written to the store before they are attempted, so nothing is
+//! lost if the connection drops or the app is closed mid-swipe.
+
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{store, ResultExt};
+use crate::profile;
+use crate::timers;
+use crate::yiff::{Choice, Error, Problem, Yiff};
+
+const KEY: &str = "outbox";
+
+/// How many times an action is attempted before it's marked as failed.
+const MAX_ATTEMPTS: u32 = 6;
+
+const IDLE: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+static OUTBOX: GlobalSignal<Vec<Item>> = GlobalSignal::new(Vec::new);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    VoteUp,
+    VoteDown,
+    Favorite,
+    Unfavorite,
+    /// Add the post to the post set with this id.
+    AddToSet(u64),
+    /// Take the post out of the post set with this id.
+    RemoveFromSet(u64),
+}
+
+impl Action {
+    async fn send(self, yiff: &Yiff, post_id: u64) -> Result<(), Error> {
+        match self {
+            Action::VoteUp => yiff.vote_up(post_id).await,
+            Action::VoteDown => yiff.vote_down(post_id).await,
+            Action::Favorite => yiff.favorite(post_id).await,
+            Action::Unfavorite => yiff.unfavorite(post_id).await,
+            Action::AddToSet(set_id) => yiff.add_to_set(set_id, post_id).await,
+            Action::RemoveFromSet(set_id) => yiff.remove_from_set(set_id, post_id).await,
+        }
+    }
+
+    fn is_vote(self) -> bool {
+        matches!(self, Action::VoteUp | Action::VoteDown)
+    }
+
+    /// Whether sending `self` makes sending `other` pointless, when both are
+    /// on the same post.
+    fn replaces(self, other: Action) -> bool {
+        match (self, other) {
+            (a, b) if a.is_vote() && b.is_vote() => true,
+            (Action::Favorite | Action::Unfavorite, Action::Favorite | Action::Unfavorite) => true,
+            (
+                Action::AddToSet(a) | Action::RemoveFromSet(a),
+                Action::AddToSet(b) | Action::RemoveFromSet(b),
+            ) => a == b,
+            _ => false,
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Action::VoteUp => "vote up".to_owned(),
+            Action::VoteDown => "vote down".to_owned(),
+            Action::Favorite => "favorite".to_owned(),
+            Action::Unfavorite => "unfavorite".to_owned(),
+            Action::AddToSet(set_id) => format!("add to set #{set_id}"),
+            Action::RemoveFromSet(set_id) => format!("remove from set #{set_id}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    id: u64,
+    /// Base URL of the instance the action was taken on.
+    site: String,
+    post_id: u64,
+    action: Action,
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    failed: bool,
+}
+
+fn save(items: &[Item]) {
+    store().set(&profile::key(KEY), &items).record_err();
+}
+
+fn modify<F>(f: F)
+where
+    F: FnOnce(&mut Vec<Item>),
+{
+    let mut items = OUTBOX.write();
+    f(&mut items);
+    save(&items);
+}
+
+/// Restores actions left over from a previous run.
+pub fn load() {
+    // TODO: Find a spawn_blocking replacement.
+    *OUTBOX.write() = store().get(&profile::key(KEY)).unwrap_or_default();
+}
+
+/// Queues `action` on `post_id` to be sent to `site`.
+///
+/// Votes replace each other, so a new vote drops any older vote on the same
+/// post that hasn't been sent yet.
+pub fn push(site: &str, post_id: u64, action: Action) {
+    modify(|items| {
+        if action.is_vote() {
+            items.retain(|i| {
+                i.failed || i.site != site || i.post_id != post_id || !i.action.is_vote()
+            });
+        }
+
+        let id = items.iter().map(|i| i.id + 1).max().unwrap_or_default();
+        items.push(Item {
+            id,
+            site: site.to_owned(),
+            post_id,
+            action,
+            attempts: 0,
+            error: None,
+            failed: false,
+        });
+    });
+}
+
+/// Puts a failed action back in line, unless a newer action on the same post
+/// has replaced it since, in which case it's dropped instead.
+pub fn retry(id: u64) {
+    modify(|items| requeue(items, id));
+}
+
+fn requeue(items: &mut Vec<Item>, id: u64) {
+    let Some(item) = items.iter().find(|i| i.id == id).cloned() else {
+        return;
+    };
+
+    let replaced = items.iter().any(|i| {
+        i.id > item.id
+            && i.site == item.site
+            && i.post_id == item.post_id
+            && i.action.replaces(item.action)
+    });
+
+    if replaced {
+        items.retain(|i| i.id != id);
+    } else if let Some(item) = items.iter_mut().find(|i| i.id == id) {
+        item.failed = false;
+        item.attempts = 0;
+    }
+}
+
+/// Drops an action without sending it.
+pub fn discard(id: u64) {
+    modify(|items| items.retain(|i| i.id != id));
+}
+
+/// Actions that take a post from the `previous` choice (if any) to `next`.
+///
+/// `is_favorited` is what the booru said when the post was loaded, and only
+/// matters when there's no previous choice.
+pub fn corrections(previous: Option<Choice>, next: Choice, is_favorited: bool) -> Vec<Action> {
+    if previous == Some(next) {
+        return Vec::new();
+    }
+
+    let favorited = match previous {
+        Some(c) => c == Choice::Favorite,
+        None => is_favorited,
+    };
+
+    let mut actions = Vec::new();
+
+    match next {
+        Choice::Favorite => {
+            if previous != Some(Choice::Like) {
+                actions.push(Action::VoteUp);
+            }
+            if !favorited {
+                actions.push(Action::Favorite);
+            }
+        }
+        Choice::Like => {
+            if previous == Some(Choice::Favorite) {
+                actions.push(Action::Unfavorite);
+            } else {
+                actions.push(Action::VoteUp);
+            }
+        }
+        Choice::Dislike => {
+            if favorited {
+                actions.push(Action::Unfavorite);
+            }
+            actions.push(Action::VoteDown);
+        }
+    }
+
+    actions
+}
+
+fn backoff(attempts: u32) -> Duration {
+    let secs = 2u64.saturating_pow(attempts);
+    Duration::from_secs(secs).min(MAX_BACKOFF)
+}
+
+/// Sends queued actions, one at a time and in order, forever.
+///
+/// `current` returns the logged in client and the base URL of its instance,
+/// or `None` while nobody is logged in. Only actions taken on that instance
+/// are sent.
+pub async fn run<F>(current: F)
+where
+    F: Fn() -> Option<(Yiff, String)>,
+{
+    let mut delay = IDLE;
+
+    loop {
+        timers::cancelable(delay).1.await.ok();
+        delay = IDLE;
+
+        let (yiff, site) = match current() {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let next = OUTBOX
+            .read()
+            .iter()
+            .find(|i| !i.failed && i.site == site)
+            .cloned();
+
+        let item = match next {
+            Some(i) => i,
+            None => continue,
+        };
+
+        match item.action.send(&yiff, item.post_id).await {
+            Ok(()) => {
+                discard(item.id);
+                delay = Duration::ZERO;
+            }
+            Err(e) if e.problem() == Problem::Unauthorized => {
+                // Not the action's fault. Try again once logged back in.
+                crate::app::note_rejection(&e);
+            }
+            Err(e) => {
+                let attempts = item.attempts + 1;
+                modify(|items| {
+                    if let Some(i) = items.iter_mut().find(|i| i.id == item.id) {
+                        i.attempts = attempts;
+                        i.error = Some(e.to_string());
+                        i.failed = attempts >= MAX_ATTEMPTS;
+                    }
+                });
+                delay = backoff(attempts);
+            }
+        }
+    }
+}
+
+/// Small counter of pending and failed actions. Renders nothing when empty.
+#[component]
+pub fn Status() -> Element {
+    let items = OUTBOX.read();
+    let failed = items.iter().filter(|i| i.failed).count();
+    let pending = items.len() - failed;
+
+    rsx! {
+        if pending > 0 {
+            span { class: "outbox-pending", title: "waiting to be sent", "⏳ {pending}" }
+        }
+        if failed > 0 {
+            span { class: "outbox-failed", title: "could not be sent", "⚠️ {failed}" }
+        }
+    }
+}
+
+/// Lists actions that gave up, with buttons to retry or discard them.
+#[component]
+pub fn Failed() -> Element {
+    let items = OUTBOX.read();
+    let failed: Vec<_> = items.iter().filter(|i| i.failed).cloned().collect();
+
+    if failed.is_empty() {
+        return None;
+    }
+
+    rsx! {
+        fieldset { class: "outbox",
+            legend { "Some ratings couldn't be sent" }
+
+            ul {
+                for item in failed {
+                    li { key: "outbox-{item.id}",
+                        "{item.action.describe()} #{item.post_id}"
+                        if let Some(ref error) = item.error {
+                            div { class: "outbox-error", "{error}" }
+                        }
+                        button {
+                            r#type: "button",
+                            onclick: move |_| retry(item.id),
+                            "Retry"
+                        }
+                        button {
+                            r#type: "button",
+                            onclick: move |_| discard(item.id),
+                            "Discard"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrections_from_each_choice() {
+        use Action::{Unfavorite, VoteDown, VoteUp};
+        use Choice::{Dislike, Like};
+
+        let cases: &[(Option<Choice>, Choice, bool, &[Action])] = &[
+            (None, Choice::Favorite, false, &[VoteUp, Action::Favorite]),
+            (None, Choice::Favorite, true, &[VoteUp]),
+            (None, Like, false, &[VoteUp]),
+            (None, Like, true, &[VoteUp]),
+            (None, Dislike, false, &[VoteDown]),
+            (None, Dislike, true, &[Unfavorite, VoteDown]),
+            (Some(Choice::Favorite), Choice::Favorite, false, &[]),
+            (Some(Choice::Favorite), Like, false, &[Unfavorite]),
+            (
+                Some(Choice::Favorite),
+                Dislike,
+                false,
+                &[Unfavorite, VoteDown],
+            ),
+            (Some(Like), Choice::Favorite, false, &[Action::Favorite]),
+            (Some(Like), Like, false, &[]),
+            (Some(Like), Dislike, true, &[VoteDown]),
+            (
+                Some(Dislike),
+                Choice::Favorite,
+                false,
+                &[VoteUp, Action::Favorite],
+            ),
+            (Some(Dislike), Like, true, &[VoteUp]),
+            (Some(Dislike), Dislike, false, &[]),
+        ];
+
+        for &(previous, next, is_favorited, expected) in cases {
+            assert_eq!(
+                corrections(previous, next, is_favorited),
+                expected,
+                "{previous:?} -> {next:?} (favorited: {is_favorited})",
+            );
+        }
+    }
+
+    fn item(id: u64, post_id: u64, action: Action, failed: bool) -> Item {
+        Item {
+            id,
+            site: "https://e621.net".to_owned(),
+            post_id,
+            action,
+            attempts: if failed { MAX_ATTEMPTS } else { 0 },
+            error: None,
+            failed,
+        }
+    }
+
+    #[test]
+    fn retry_requeues_failed_action() {
+        let mut items = vec![item(0, 1, Action::VoteUp, true)];
+        requeue(&mut items, 0);
+        assert_eq!(items, [item(0, 1, Action::VoteUp, false)]);
+    }
+
+    #[test]
+    fn retry_drops_replaced_action() {
+        let mut items = vec![
+            item(0, 1, Action::VoteUp, true),
+            item(1, 1, Action::VoteDown, false),
+        ];
+        requeue(&mut items, 0);
+        assert_eq!(items, [item(1, 1, Action::VoteDown, false)]);
+
+        let mut items = vec![
+            item(0, 1, Action::Favorite, true),
+            item(1, 1, Action::Unfavorite, true),
+        ];
+        requeue(&mut items, 0);
+        assert_eq!(items, [item(1, 1, Action::Unfavorite, true)]);
+
+        let mut items = vec![
+            item(0, 1, Action::AddToSet(7), true),
+            item(1, 1, Action::RemoveFromSet(7), false),
+        ];
+        requeue(&mut items, 0);
+        assert_eq!(items, [item(1, 1, Action::RemoveFromSet(7), false)]);
+    }
+
+    #[test]
+    fn retry_keeps_unrelated_action() {
+        let mut items = vec![
+            item(0, 1, Action::VoteUp, true),
+            item(1, 2, Action::VoteDown, false),
+            item(2, 1, Action::Favorite, false),
+            item(3, 1, Action::AddToSet(7), false),
+        ];
+        requeue(&mut items, 0);
+        assert!(!items[0].failed);
+        assert_eq!(items.len(), 4);
+
+        let mut items = vec![
+            item(0, 1, Action::AddToSet(7), true),
+            item(1, 1, Action::RemoveFromSet(8), false),
+        ];
+        requeue(&mut items, 0);
+        assert!(!items[0].failed);
+    }
+}
@@ -0,0 +1,95 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Finding a pool to read, by id or by name.
+
+use dioxus::prelude::*;
+
+use crate::app::ResultExt;
+use crate::yiff::{Pool, Yiff};
+
+#[component]
+pub fn Picker(yiff: ReadOnlySignal<Yiff>, onpick: EventHandler<Pool>) -> Element {
+    let mut needle = use_signal(String::new);
+    let mut found = use_signal(|| Option::<Vec<Pool>>::None);
+    let mut busy = use_signal(|| false);
+
+    let find = move |_| {
+        let text = needle.peek().trim().to_owned();
+        if text.is_empty() {
+            return;
+        }
+
+        busy.set(true);
+        spawn(async move {
+            let yiff = yiff.peek().clone();
+
+            // Anything numeric (with or without a leading `#`) is an id.
+            let pools = match text.trim_start_matches('#').parse::<u64>() {
+                Ok(id) => yiff
+                    .pool(id)
+                    .await
+                    .record_err()
+                    .map(|p| p.into_iter().collect()),
+                Err(_) => yiff.pools(text).await.record_err(),
+            };
+
+            busy.set(false);
+            match pools.as_deref() {
+                Some([only]) => onpick.call(only.clone()),
+                _ => found.set(pools),
+            }
+        });
+    };
+
+    let style = include_str!("pool.css");
+    rsx! {
+        style { "{style}" }
+        form { class: "pool-picker", prevent_default: "onsubmit", onsubmit: find,
+            input {
+                r#type: "search",
+                placeholder: "pool id or name",
+                value: "{needle}",
+                oninput: move |evt| needle.set(evt.value())
+            }
+            button { r#type: "submit", disabled: busy(), "Read a pool" }
+        }
+        match &*found.read() {
+            Some(pools) if pools.is_empty() => rsx! {
+                div { class: "pool-results", "No pools found." }
+            },
+            Some(pools) => rsx! {
+                ul { class: "pool-results",
+                    for pool in pools.iter() {
+                        li { key: "{pool.id}",
+                            button {
+                                r#type: "button",
+                                onclick: {
+                                    let pool = pool.clone();
+                                    move |_| onpick.call(pool.clone())
+                                },
+                                "{pool.title()} ({pool.post_ids.len()} pages)"
+                            }
+                        }
+                    }
+                }
+            },
+            None => None,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Named profiles, each with its own account and saved data.
+//!
+//! Everything a profile saves goes under a key made by [`key`]. The first
+//! profile uses the bare keys, so data saved before profiles existed becomes
+//! part of it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{store, ResultExt};
+
+const KEY: &str = "profiles";
+
+/// The profile whose keys are used by [`key`].
+static CURRENT: AtomicU64 = AtomicU64::new(0);
+
+/// The chosen profile, or `None` while waiting for the user to pick one.
+pub static ACTIVE: GlobalSignal<Option<u64>> = GlobalSignal::new(|| None);
+
+static PROFILES: GlobalSignal<Profiles> = GlobalSignal::new(Profiles::default);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Profiles {
+    list: Vec<Profile>,
+    /// The profile used most recently, chosen first in the picker.
+    last: u64,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Profiles {
+            list: vec![Profile {
+                id: 0,
+                name: "Default".to_owned(),
+            }],
+            last: 0,
+        }
+    }
+}
+
+impl Profiles {
+    async fn save(self) {
+        // TODO: Find a spawn_blocking replacement.
+        store().set(KEY, &self).record_err();
+    }
+
+    fn add(&mut self, name: String) -> u64 {
+        let id = self.list.iter().map(|p| p.id).max().unwrap_or(0) + 1;
+        self.list.push(Profile { id, name });
+        id
+    }
+}
+
+/// Namespaces a store key to the current profile.
+pub fn key(name: &str) -> String {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => name.to_owned(),
+        id => format!("profile:{id}:{name}"),
+    }
+}
+
+/// Reads the saved profiles. With only one, it's chosen right away.
+pub async fn load() {
+    // TODO: Find a spawn_blocking replacement.
+    let profiles: Profiles = store().get(KEY).unwrap_or_default();
+
+    if let [only] = &profiles.list[..] {
+        let id = only.id;
+        *PROFILES.write() = profiles;
+        switch_to(id);
+    } else {
+        *PROFILES.write() = profiles;
+    }
+}
+
+/// Switches to the profile `id`, reloading everything saved under it.
+pub fn switch_to(id: u64) {
+    CURRENT.store(id, Ordering::SeqCst);
+
+    let mut profiles = PROFILES.write();
+    if profiles.last != id {
+        profiles.last = id;
+        spawn_forever(profiles.clone().save());
+    }
+
+    *ACTIVE.write() = Some(id);
+}
+
+fn create(name: String) {
+    let name = name.trim().to_owned();
+    if name.is_empty() {
+        return;
+    }
+
+    let id = {
+        let mut profiles = PROFILES.write();
+        let id = profiles.add(name);
+        spawn_forever(profiles.clone().save());
+        id
+    };
+
+    switch_to(id);
+}
+
+/// Shown at startup when there's more than one profile.
+#[component]
+pub fn Chooser() -> Element {
+    let profiles = PROFILES.read();
+    let style = include_str!("profile.css");
+
+    rsx! {
+        style { "{style}" }
+        div { class: "profile-chooser",
+            h2 { "Who's browsing?" }
+            for profile in profiles.list.iter() {
+                button {
+                    key: "{profile.id}",
+                    r#type: "button",
+                    autofocus: profile.id == profiles.last,
+                    onclick: {
+                        let id = profile.id;
+                        move |_| switch_to(id)
+                    },
+                    "{profile.name}"
+                }
+            }
+        }
+    }
+}
+
+/// Picks another profile, or makes a new one.
+#[component]
+pub fn Switcher() -> Element {
+    let mut name = use_signal(String::new);
+    let profiles = PROFILES.read();
+    let current = ACTIVE.read().unwrap_or_default();
+
+    rsx! {
+        select {
+            onchange: move |evt| {
+                if let Ok(id) = evt.value().parse() {
+                    switch_to(id);
+                }
+            },
+            for profile in profiles.list.iter() {
+                option {
+                    key: "{profile.id}",
+                    value: "{profile.id}",
+                    selected: profile.id == current,
+                    "{profile.name}"
+                }
+            }
+        }
+        input {
+            r#type: "text",
+            placeholder: "new profile",
+            value: "{name}",
+            oninput: move |evt| name.set(evt.value())
+        }
+        button {
+            r#type: "button",
+            tabindex: "-1",
+            disabled: name.read().trim().is_empty(),
+            onclick: move |_| create(name.peek().clone()),
+            "Add"
+        }
+    }
+}
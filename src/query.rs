@@ -0,0 +1,730 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Parser for e621's search syntax.
+//!
+//! Handles negation (`-wolf`), or-groups (`~fox ~wolf`), parenthesized
+//! groups (`-( fox wolf )`), metatags with comparisons (`score:>=10`,
+//! `id:100..200`), wildcards (`wolf*`) and quoted metatag values
+//! (`source:"some site"`). Also suggests how to finish a metatag that's
+//! being typed, without asking the site.
+
+use std::fmt;
+
+use snafu::Snafu;
+
+/// Metatags, as opposed to tags that happen to contain a colon (like `:3`.)
+const METATAGS: &[&str] = &[
+    "approver",
+    "child",
+    "comment_count",
+    "commenter",
+    "date",
+    "delreason",
+    "description",
+    "downvote",
+    "duration",
+    "fav",
+    "favcount",
+    "favoritedby",
+    "filesize",
+    "filetype",
+    "height",
+    "id",
+    "inpool",
+    "ischild",
+    "isparent",
+    "limit",
+    "md5",
+    "mpixels",
+    "noter",
+    "order",
+    "parent",
+    "pool",
+    "randseed",
+    "rating",
+    "ratio",
+    "score",
+    "set",
+    "source",
+    "status",
+    "tagcount",
+    "type",
+    "upvote",
+    "user",
+    "voted",
+    "votedown",
+    "votedup",
+    "width",
+];
+
+/// Metatags whose values are whole numbers, or ranges of them.
+const NUMERIC: &[&str] = &[
+    "comment_count",
+    "favcount",
+    "height",
+    "id",
+    "randseed",
+    "score",
+    "tagcount",
+    "width",
+];
+
+#[derive(Debug, Clone, Eq, PartialEq, Snafu)]
+pub enum Error {
+    #[snafu(display("A quote in `{token}` is never closed"))]
+    UnclosedQuote { token: String },
+    #[snafu(display("A `(` is never closed"))]
+    UnclosedGroup,
+    #[snafu(display("A `)` doesn't close anything"))]
+    UnopenedGroup,
+    #[snafu(display("`( )` needs something between the brackets"))]
+    EmptyGroup,
+    #[snafu(display("`{token}` needs a tag after it"))]
+    Dangling { token: String },
+    #[snafu(display("`{token}` can only start with one of `-` or `~`"))]
+    DoublePrefix { token: String },
+    #[snafu(display("`{key}:` needs a value"))]
+    MissingValue { key: String },
+    #[snafu(display("`{key}:` expects a number, not `{value}`"))]
+    NotANumber { key: String, value: String },
+    #[snafu(display("Quotes only work in metatag values, like `source:\"...\"`"))]
+    StrayQuote,
+}
+
+/// Whether a [`Clause`] is required, forbidden, or one of several options.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Prefix {
+    Must,
+    /// `-`
+    Not,
+    /// `~`
+    Any,
+}
+
+/// How a metatag's value is compared.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Op {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `a..b`, `..b` or `a..`.
+    Range,
+}
+
+impl Op {
+    /// Splits the comparison off the front of a metatag's value, like the
+    /// `>=` in `score:>=10`.
+    pub fn split(value: &str) -> (Self, &str) {
+        if let Some(v) = value.strip_prefix(">=") {
+            (Op::Ge, v)
+        } else if let Some(v) = value.strip_prefix("<=") {
+            (Op::Le, v)
+        } else if let Some(v) = value.strip_prefix('>') {
+            (Op::Gt, v)
+        } else if let Some(v) = value.strip_prefix('<') {
+            (Op::Lt, v)
+        } else if value.contains("..") {
+            (Op::Range, value)
+        } else {
+            (Op::Eq, value)
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq | Op::Range => "",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Term {
+    /// A tag name, possibly with `*` wildcards.
+    Tag(String),
+    Meta {
+        key: String,
+        op: Op,
+        value: String,
+    },
+    Group(Vec<Clause>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Clause {
+    pub prefix: Prefix,
+    pub term: Term,
+}
+
+/// A parsed search, ready to be turned back into normalized terms.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Query {
+    pub clauses: Vec<Clause>,
+}
+
+impl Query {
+    /// Each top level clause, written out the way e621 expects.
+    pub fn terms(&self) -> Vec<String> {
+        self.clauses.iter().map(Clause::to_string).collect()
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_all(f, &self.clauses)
+    }
+}
+
+fn write_all(f: &mut fmt::Formatter<'_>, clauses: &[Clause]) -> fmt::Result {
+    for (index, clause) in clauses.iter().enumerate() {
+        if index > 0 {
+            f.write_str(" ")?;
+        }
+        write!(f, "{clause}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Clause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.prefix {
+            Prefix::Must => (),
+            Prefix::Not => f.write_str("-")?,
+            Prefix::Any => f.write_str("~")?,
+        }
+        write!(f, "{}", self.term)
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Tag(name) => f.write_str(name),
+            Term::Meta { key, op, value } if value.contains(char::is_whitespace) => {
+                write!(f, "{key}:{}\"{value}\"", op.as_str())
+            }
+            Term::Meta { key, op, value } => write!(f, "{key}:{}{value}", op.as_str()),
+            Term::Group(clauses) => {
+                f.write_str("( ")?;
+                write_all(f, clauses)?;
+                f.write_str(" )")
+            }
+        }
+    }
+}
+
+/// Splits `input` on whitespace, except inside quotes.
+fn tokenize(input: &str) -> Result<Vec<String>, Error> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                token.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+
+    if quoted {
+        return UnclosedQuoteSnafu { token }.fail();
+    }
+
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn parse_clauses<I>(tokens: &mut I, nested: bool) -> Result<Vec<Clause>, Error>
+where
+    I: Iterator<Item = String>,
+{
+    let mut clauses = Vec::new();
+
+    loop {
+        let token = match tokens.next() {
+            Some(t) => t,
+            None if nested => return UnclosedGroupSnafu.fail(),
+            None => return Ok(clauses),
+        };
+
+        if token == ")" {
+            if !nested {
+                return UnopenedGroupSnafu.fail();
+            }
+            return Ok(clauses);
+        }
+
+        let (prefix, rest) = match token.chars().next() {
+            Some('-') => (Prefix::Not, &token[1..]),
+            Some('~') => (Prefix::Any, &token[1..]),
+            _ => (Prefix::Must, &token[..]),
+        };
+
+        if rest.is_empty() {
+            return DanglingSnafu { token }.fail();
+        }
+
+        if prefix != Prefix::Must && rest.starts_with(['-', '~']) {
+            return DoublePrefixSnafu { token }.fail();
+        }
+
+        let term = match rest {
+            "(" => {
+                let inner = parse_clauses(tokens, true)?;
+                if inner.is_empty() {
+                    return EmptyGroupSnafu.fail();
+                }
+                Term::Group(inner)
+            }
+            _ => parse_term(rest)?,
+        };
+
+        clauses.push(Clause { prefix, term });
+    }
+}
+
+fn parse_term(text: &str) -> Result<Term, Error> {
+    if let Some((key, value)) = text.split_once(':') {
+        let key = key.to_lowercase();
+        if METATAGS.contains(&key.as_str()) {
+            return parse_meta(key, value);
+        }
+    }
+
+    if text.contains('"') {
+        return StrayQuoteSnafu.fail();
+    }
+
+    Ok(Term::Tag(text.to_lowercase()))
+}
+
+fn parse_meta(key: String, value: &str) -> Result<Term, Error> {
+    let (op, value) = Op::split(value);
+
+    let value = match value.strip_prefix('"') {
+        Some(v) => v.strip_suffix('"').unwrap_or(v),
+        None => value,
+    };
+
+    if value.is_empty() {
+        return MissingValueSnafu { key }.fail();
+    }
+
+    if NUMERIC.contains(&key.as_str()) {
+        let numbers = match op {
+            Op::Range => value.split("..").filter(|n| !n.is_empty()).collect(),
+            // Lists, like `id:1,2,3`.
+            _ => value.split(',').collect::<Vec<_>>(),
+        };
+
+        if numbers.is_empty() || numbers.iter().any(|n| n.parse::<i64>().is_err()) {
+            return NotANumberSnafu {
+                key,
+                value: value.to_owned(),
+            }
+            .fail();
+        }
+    }
+
+    Ok(Term::Meta {
+        key,
+        op,
+        value: value.to_owned(),
+    })
+}
+
+/// Values `rating:` accepts, with what they mean.
+const RATINGS: &[(&str, &str)] = &[("s", "safe"), ("q", "questionable"), ("e", "explicit")];
+
+/// Values `order:` accepts, with what they mean.
+const ORDERS: &[(&str, &str)] = &[
+    ("id", "oldest first"),
+    ("id_desc", "newest first"),
+    ("score", "highest score"),
+    ("score_asc", "lowest score"),
+    ("favcount", "most favorites"),
+    ("favcount_asc", "fewest favorites"),
+    ("created", "newest upload"),
+    ("created_asc", "oldest upload"),
+    ("updated", "recently updated"),
+    ("comment_count", "most comments"),
+    ("tagcount", "most tags"),
+    ("tagcount_asc", "fewest tags"),
+    ("mpixels", "largest"),
+    ("mpixels_asc", "smallest"),
+    ("filesize", "biggest file"),
+    ("filesize_asc", "smallest file"),
+    ("duration", "longest"),
+    ("duration_asc", "shortest"),
+    ("landscape", "widest"),
+    ("portrait", "tallest"),
+    ("random", "shuffled"),
+    ("hot", "popular lately"),
+];
+
+/// Values `type:` and `filetype:` accept, with what they mean.
+const FILE_TYPES: &[(&str, &str)] = &[
+    ("jpg", "image"),
+    ("png", "image"),
+    ("gif", "animation"),
+    ("webm", "video"),
+    ("swf", "flash"),
+];
+
+/// Values `status:` accepts, with what they mean.
+const STATUSES: &[(&str, &str)] = &[
+    ("active", "approved"),
+    ("pending", "awaiting approval"),
+    ("flagged", "flagged for deletion"),
+    ("deleted", "deleted"),
+    ("any", "any status"),
+];
+
+/// Relative values `date:` accepts, with what they mean.
+const DATES: &[(&str, &str)] = &[
+    ("today", "since midnight"),
+    ("yesterday", "the day before today"),
+    ("week", "the last 7 days"),
+    ("month", "the last 30 days"),
+    ("year", "the last 365 days"),
+    ("decade", "the last 10 years"),
+    ("yesterweek", "the week before last"),
+    ("yestermonth", "the month before last"),
+    ("yesteryear", "the year before last"),
+];
+
+/// Units for `date:N_<unit>_ago`.
+const DATE_UNITS: &[&str] = &["days", "weeks", "months", "years"];
+
+/// Comparisons the numeric metatags (and `date:`) accept.
+const COMPARISONS: &[(&str, &str)] = &[
+    (">=", "at least"),
+    (">", "more than"),
+    ("<=", "at most"),
+    ("<", "less than"),
+];
+
+/// A way to finish a term that's being typed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Completion {
+    /// The whole term, once finished this way.
+    pub text: String,
+    pub about: &'static str,
+
+    /// Whether there's more to type after this, like a value after `rating:`.
+    pub partial: bool,
+}
+
+/// Whether `term` (without a `-` or `~`) is a metatag, so isn't worth
+/// looking up as a tag.
+pub fn is_meta(term: &str) -> bool {
+    match term.split_once(':') {
+        Some((key, _)) => METATAGS.contains(&key.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Metatag keys and values that could finish `term` (without a `-` or `~`.)
+pub fn complete(term: &str) -> Vec<Completion> {
+    let term = term.trim().to_lowercase();
+    let Some((key, value)) = term.split_once(':') else {
+        // One letter matches too many keys to be any use.
+        if term.len() < 2 {
+            return Vec::new();
+        }
+
+        return METATAGS
+            .iter()
+            .filter(|k| k.starts_with(&term))
+            .map(|k| Completion {
+                text: format!("{k}:"),
+                about: "metatag",
+                partial: true,
+            })
+            .collect();
+    };
+
+    let finished = |values: &[(&str, &'static str)]| -> Vec<Completion> {
+        values
+            .iter()
+            .filter(|(v, _)| v.starts_with(value))
+            .map(|&(v, about)| Completion {
+                text: format!("{key}:{v}"),
+                about,
+                partial: false,
+            })
+            .collect()
+    };
+
+    let compared = || -> Vec<Completion> {
+        match value {
+            "" => COMPARISONS
+                .iter()
+                .map(|&(op, about)| Completion {
+                    text: format!("{key}:{op}"),
+                    about,
+                    partial: true,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    };
+
+    match key {
+        "rating" => finished(RATINGS),
+        "order" => finished(ORDERS),
+        "type" | "filetype" => finished(FILE_TYPES),
+        "status" => finished(STATUSES),
+        "date" => {
+            let mut found = compared();
+            let days = value.trim_start_matches(['<', '>', '=']);
+            let op = &value[..value.len() - days.len()];
+
+            found.extend(
+                DATES
+                    .iter()
+                    .filter(|(d, _)| d.starts_with(days))
+                    .map(|&(d, about)| Completion {
+                        text: format!("{key}:{op}{d}"),
+                        about,
+                        partial: false,
+                    }),
+            );
+
+            // `date:3` becomes `date:3_days_ago`, and so on.
+            let count = days.split('_').next().unwrap_or_default();
+            if !count.is_empty() && count.bytes().all(|b| b.is_ascii_digit()) {
+                found.extend(
+                    DATE_UNITS
+                        .iter()
+                        .map(|unit| format!("{count}_{unit}_ago"))
+                        .filter(|d| d.starts_with(days))
+                        .map(|d| Completion {
+                            text: format!("{key}:{op}{d}"),
+                            about: "ago",
+                            partial: false,
+                        }),
+                );
+            }
+            found
+        }
+        "filesize" | "mpixels" | "duration" | "ratio" => compared(),
+        _ if NUMERIC.contains(&key) && key != "randseed" => compared(),
+        _ => Vec::new(),
+    }
+}
+
+/// Parses a search, whether it's one term or several.
+pub fn parse(input: &str) -> Result<Query, Error> {
+    let tokens = tokenize(input)?;
+    let clauses = parse_clauses(&mut tokens.into_iter(), false)?;
+    Ok(Query { clauses })
+}
+
+/// Parses each of `entries` (as typed into separate boxes) into one search.
+pub fn parse_all<I, S>(entries: I) -> Result<Query, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut query = Query::default();
+    for entry in entries {
+        query.clauses.extend(parse(entry.as_ref())?.clauses);
+    }
+    Ok(query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_normalizes() {
+        let cases = [
+            ("wolf", "wolf"),
+            ("  Wolf   Fox ", "wolf fox"),
+            ("-wolf ~fox ~cat", "-wolf ~fox ~cat"),
+            ("wolf*", "wolf*"),
+            (":3", ":3"),
+            ("Score:>=10", "score:>=10"),
+            ("score:<0", "score:<0"),
+            ("id:100..200", "id:100..200"),
+            ("id:..200", "id:..200"),
+            ("id:1,2,3", "id:1,2,3"),
+            ("source:\"some site\"", "source:\"some site\""),
+            ("source:\"site\"", "source:site"),
+            ("-( fox wolf )", "-( fox wolf )"),
+            ("~( fox -( wolf cat ) )", "~( fox -( wolf cat ) )"),
+        ];
+
+        for (input, expected) in cases {
+            let query = parse(input).unwrap_or_else(|e| panic!("{input}: {e}"));
+            assert_eq!(query.to_string(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn parse_metatags() {
+        let cases = [
+            ("score:>=10", "score", Op::Ge, "10"),
+            ("score:<=10", "score", Op::Le, "10"),
+            ("score:>10", "score", Op::Gt, "10"),
+            ("score:<10", "score", Op::Lt, "10"),
+            ("score:10", "score", Op::Eq, "10"),
+            ("id:1..5", "id", Op::Range, "1..5"),
+            ("rating:e", "rating", Op::Eq, "e"),
+        ];
+
+        for (input, key, op, value) in cases {
+            let expected = Term::Meta {
+                key: key.to_owned(),
+                op,
+                value: value.to_owned(),
+            };
+            let query = parse(input).unwrap();
+            assert_eq!(query.clauses[0].term, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn parse_rejects() {
+        let cases = [
+            (
+                "source:\"some site",
+                Error::UnclosedQuote {
+                    token: "source:\"some site".to_owned(),
+                },
+            ),
+            ("( wolf", Error::UnclosedGroup),
+            ("wolf )", Error::UnopenedGroup),
+            ("( )", Error::EmptyGroup),
+            (
+                "wolf -",
+                Error::Dangling {
+                    token: "-".to_owned(),
+                },
+            ),
+            (
+                "-~wolf",
+                Error::DoublePrefix {
+                    token: "-~wolf".to_owned(),
+                },
+            ),
+            (
+                "score:",
+                Error::MissingValue {
+                    key: "score".to_owned(),
+                },
+            ),
+            (
+                "score:>=high",
+                Error::NotANumber {
+                    key: "score".to_owned(),
+                    value: "high".to_owned(),
+                },
+            ),
+            (
+                "id:..",
+                Error::NotANumber {
+                    key: "id".to_owned(),
+                    value: "..".to_owned(),
+                },
+            ),
+            ("\"wolf\"", Error::StrayQuote),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse(input), Err(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn parse_all_joins_entries() {
+        let query = parse_all(["wolf -fox", "rating:s"]).unwrap();
+        assert_eq!(query.terms(), ["wolf", "-fox", "rating:s"]);
+    }
+
+    fn texts(term: &str) -> Vec<String> {
+        complete(term).into_iter().map(|c| c.text).collect()
+    }
+
+    #[test]
+    fn complete_finishes_terms() {
+        let cases: &[(&str, &[&str])] = &[
+            ("w", &[]),
+            ("wolf", &[]),
+            ("fav", &["fav:", "favcount:", "favoritedby:"]),
+            ("rating:", &["rating:s", "rating:q", "rating:e"]),
+            ("Rating:Q", &["rating:q"]),
+            ("order:score", &["order:score", "order:score_asc"]),
+            ("type:w", &["type:webm"]),
+            ("status:del", &["status:deleted"]),
+            ("score:", &["score:>=", "score:>", "score:<=", "score:<"]),
+            ("score:5", &[]),
+            ("randseed:", &[]),
+            (
+                "date:yester",
+                &[
+                    "date:yesterday",
+                    "date:yesterweek",
+                    "date:yestermonth",
+                    "date:yesteryear",
+                ],
+            ),
+            ("date:>=to", &["date:>=today"]),
+            ("date:3_w", &["date:3_weeks_ago"]),
+            ("user:", &[]),
+        ];
+
+        for (term, expected) in cases {
+            assert_eq!(texts(term), *expected, "{term}");
+        }
+    }
+
+    #[test]
+    fn complete_marks_partial_terms() {
+        assert!(complete("rati").iter().all(|c| c.partial));
+        assert!(complete("score:").iter().all(|c| c.partial));
+        assert!(complete("rating:").iter().all(|c| !c.partial));
+    }
+
+    #[test]
+    fn meta_is_only_known_keys() {
+        assert!(is_meta("score:>5"));
+        assert!(is_meta("Rating:e"));
+        assert!(!is_meta(":3"));
+        assert!(!is_meta("wolf"));
+    }
+}
@@ -0,0 +1,318 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Searches the user has named, to run again with one tap.
+
+use chrono::{DateTime, Utc};
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{store, ResultExt};
+use crate::filters::{self, Filters};
+use crate::profile;
+use crate::tag;
+use crate::yiff::Order;
+
+const KEY: &str = "saved-searches";
+
+static SAVED: GlobalSignal<Vec<SavedSearch>> = GlobalSignal::new(Vec::new);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SavedSearch {
+    pub id: u64,
+    pub name: String,
+    pub terms: Vec<String>,
+    #[serde(default)]
+    pub order: Order,
+    #[serde(default)]
+    pub last_run: Option<LastRun>,
+    /// Used instead of the default filters, when set.
+    #[serde(default)]
+    pub filters: Option<Filters>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LastRun {
+    pub date: DateTime<Utc>,
+    /// How many posts came up before the user stopped.
+    pub posts: usize,
+}
+
+fn modify<F>(f: F)
+where
+    F: FnOnce(&mut Vec<SavedSearch>),
+{
+    let mut saved = SAVED.write();
+    f(&mut saved);
+    // TODO: Find a spawn_blocking replacement.
+    store().set(&profile::key(KEY), &*saved).record_err();
+}
+
+/// Reads the current profile's saved searches.
+pub fn load() {
+    // TODO: Find a spawn_blocking replacement.
+    *SAVED.write() = store().get(&profile::key(KEY)).unwrap_or_default();
+}
+
+fn add(name: String, terms: Vec<String>, order: Order) {
+    modify(|saved| {
+        let id = saved.iter().map(|s| s.id + 1).max().unwrap_or_default();
+        saved.push(SavedSearch {
+            id,
+            name,
+            terms,
+            order,
+            last_run: None,
+            filters: None,
+        });
+    });
+}
+
+fn edit<F>(id: u64, f: F)
+where
+    F: FnOnce(&mut SavedSearch),
+{
+    modify(|saved| {
+        if let Some(search) = saved.iter_mut().find(|s| s.id == id) {
+            f(search);
+        }
+    });
+}
+
+/// Moves the saved search `id` by `offset` places in the list.
+fn shift(id: u64, offset: isize) {
+    modify(|saved| {
+        let from = match saved.iter().position(|s| s.id == id) {
+            Some(f) => f,
+            None => return,
+        };
+
+        let to = from.saturating_add_signed(offset).min(saved.len() - 1);
+        let search = saved.remove(from);
+        saved.insert(to, search);
+    });
+}
+
+fn delete(id: u64) {
+    modify(|saved| saved.retain(|s| s.id != id));
+}
+
+/// Notes that the saved search `id` was run just now, and `posts` posts came
+/// up so far.
+pub fn record_run(id: u64, posts: usize) {
+    edit(id, |search| {
+        search.last_run = Some(LastRun {
+            date: Utc::now(),
+            posts,
+        });
+    });
+}
+
+/// The tags in `search`, and how it went last time.
+fn about(search: &SavedSearch) -> String {
+    let mut terms = search.terms.join(" ");
+    if search.order != Order::Random {
+        terms = format!("{terms} ({})", search.order.name().to_lowercase());
+    }
+
+    match &search.last_run {
+        Some(run) => format!(
+            "{terms} · last run {}, {} posts",
+            run.date.format("%Y-%m-%d"),
+            run.posts
+        ),
+        None => terms,
+    }
+}
+
+#[component]
+pub fn List(
+    entries: tag::Entries,
+    /// Sort order for newly saved searches.
+    order: Order,
+    /// Filters for searches that don't have their own.
+    defaults: Filters,
+    onlaunch: EventHandler<SavedSearch>,
+) -> Element {
+    let mut name = use_signal(String::new);
+    let mut editing = use_signal(|| Option::<u64>::None);
+    let saved = SAVED.read();
+
+    let style = include_str!("saved.css");
+    rsx! {
+        style { "{style}" }
+        div { class: "saved-searches",
+            form {
+                class: "saved-new",
+                prevent_default: "onsubmit",
+                onsubmit: move |_| {
+                    let terms = entries.terms();
+                    let text = name.peek().trim().to_owned();
+                    if text.is_empty() || terms.is_empty() {
+                        return;
+                    }
+                    add(text, terms, order);
+                    name.set(String::new());
+                },
+                input {
+                    r#type: "text",
+                    placeholder: "name these tags",
+                    value: "{name}",
+                    oninput: move |evt| name.set(evt.value())
+                }
+                button { r#type: "submit", disabled: name.read().trim().is_empty(), "Save search" }
+            }
+
+            ul {
+                for (position, search) in saved.iter().enumerate() {
+                    li { key: "{search.id}",
+                        if editing() == Some(search.id) {
+                            input {
+                                r#type: "text",
+                                value: "{search.name}",
+                                onchange: {
+                                    let id = search.id;
+                                    move |evt: Event<FormData>| {
+                                        let value = evt.value();
+                                        edit(id, |s| s.name = value.trim().to_owned());
+                                    }
+                                }
+                            }
+                            button {
+                                r#type: "button",
+                                title: "use the tags above instead",
+                                onclick: {
+                                    let id = search.id;
+                                    move |_| {
+                                        let terms = entries.terms();
+                                        if !terms.is_empty() {
+                                            edit(id, |s| s.terms = terms);
+                                        }
+                                    }
+                                },
+                                "Use current tags"
+                            }
+                            select {
+                                onchange: {
+                                    let id = search.id;
+                                    move |evt: Event<FormData>| {
+                                        if let Some(order) = Order::from_term(&evt.value()) {
+                                            edit(id, |s| s.order = order);
+                                        }
+                                    }
+                                },
+                                for order in Order::ALL {
+                                    option {
+                                        value: order.term(),
+                                        selected: order == search.order,
+                                        "{order.name()}"
+                                    }
+                                }
+                            }
+                            button {
+                                r#type: "button",
+                                title: "move up",
+                                disabled: position == 0,
+                                onclick: {
+                                    let id = search.id;
+                                    move |_| shift(id, -1)
+                                },
+                                "↑"
+                            }
+                            button {
+                                r#type: "button",
+                                title: "move down",
+                                disabled: position + 1 == saved.len(),
+                                onclick: {
+                                    let id = search.id;
+                                    move |_| shift(id, 1)
+                                },
+                                "↓"
+                            }
+                            button {
+                                r#type: "button",
+                                title: "delete",
+                                onclick: {
+                                    let id = search.id;
+                                    move |_| {
+                                        delete(id);
+                                        editing.set(None);
+                                    }
+                                },
+                                "🗑"
+                            }
+                            button { r#type: "button", onclick: move |_| editing.set(None), "Done" }
+                            div { class: "saved-filters",
+                                label {
+                                    input {
+                                        r#type: "checkbox",
+                                        checked: search.filters.is_some(),
+                                        oninput: {
+                                            let id = search.id;
+                                            let defaults = defaults.clone();
+                                            move |_| {
+                                                let defaults = defaults.clone();
+                                                edit(id, |s| {
+                                                    s.filters = match s.filters {
+                                                        Some(_) => None,
+                                                        None => Some(defaults),
+                                                    };
+                                                });
+                                            }
+                                        }
+                                    }
+                                    "Use its own filters"
+                                }
+                                if let Some(own) = &search.filters {
+                                    filters::Form {
+                                        filters: own.clone(),
+                                        onchange: {
+                                            let id = search.id;
+                                            move |f: Filters| edit(id, |s| s.filters = Some(f))
+                                        }
+                                    }
+                                }
+                            }
+                        } else {
+                            button {
+                                r#type: "button",
+                                class: "saved-launch",
+                                onclick: {
+                                    let search = search.clone();
+                                    move |_| onlaunch.call(search.clone())
+                                },
+                                "{search.name}"
+                            }
+                            button {
+                                r#type: "button",
+                                title: "edit",
+                                onclick: {
+                                    let id = search.id;
+                                    move |_| editing.set(Some(id))
+                                },
+                                "✎"
+                            }
+                        }
+                        div { class: "saved-about", "{about(search)}" }
+                    }
+                }
+            }
+        }
+    }
+}
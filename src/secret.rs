@@ -0,0 +1,233 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Encryption for secrets kept in the store, like API keys.
+//!
+//! Secrets are sealed with ChaCha20-Poly1305, using a key derived (with
+//! PBKDF2-HMAC-SHA256) from either:
+//!
+//!  - the user's passphrase, which is never stored, or
+//!  - a random device key kept elsewhere in the store.
+//!
+//! The device key only keeps the secret from being read straight out of its
+//! entry. Anyone who can read the whole store can still unseal it, which is
+//! what the passphrase is for.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::prelude::*;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use serde::{Deserialize, Serialize};
+
+use sha2::Sha256;
+
+use snafu::{ensure, ResultExt, Snafu};
+
+use crate::app::store;
+
+/// Version written by [`seal`].
+const VERSION: u8 = 1;
+
+const DEVICE_KEY: &str = "device-key";
+
+/// PBKDF2 rounds when deriving from a passphrase.
+const PASSPHRASE_ROUNDS: u32 = 210_000;
+
+/// The device key is already random, so there's nothing to stretch.
+const DEVICE_ROUNDS: u32 = 1;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("That passphrase doesn't unlock your API key"))]
+    Wrong,
+    #[snafu(display("A passphrase is needed to unlock your API key"))]
+    NeedPassphrase,
+    #[snafu(display("Don't know how to unseal version {version} secrets"))]
+    Version { version: u8 },
+    #[snafu(display("The sealed secret is damaged: {source}"))]
+    Damaged { source: base64::DecodeError },
+    #[snafu(display("Couldn't get random bytes: {source}"))]
+    Random { source: getrandom::Error },
+    #[snafu(display("Couldn't save the device key: {message}"))]
+    DeviceKey { message: String },
+}
+
+/// A secret as written to the store.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Stored {
+    Sealed(Sealed),
+    /// Written before secrets were encrypted. Read, but never written.
+    Plain(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Sealed {
+    version: u8,
+    /// Whether the key was derived from a passphrase, instead of the
+    /// device key.
+    pub passphrase: bool,
+    rounds: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn random<const N: usize>() -> Result<[u8; N], Error> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).context(RandomSnafu)?;
+    Ok(bytes)
+}
+
+fn device_key() -> Result<Vec<u8>, Error> {
+    // TODO: Find a spawn_blocking replacement.
+    let mut store = store();
+
+    let existing = store
+        .get::<String>(DEVICE_KEY)
+        .ok()
+        .and_then(|k| STANDARD.decode(k).ok());
+
+    if let Some(key) = existing {
+        return Ok(key);
+    }
+
+    let key = random::<32>()?;
+    store.set(DEVICE_KEY, &STANDARD.encode(key)).map_err(|e| {
+        DeviceKeySnafu {
+            message: e.to_string(),
+        }
+        .build()
+    })?;
+    Ok(key.to_vec())
+}
+
+fn cipher(passphrase: Option<&str>, salt: &[u8], rounds: u32) -> Result<ChaCha20Poly1305, Error> {
+    let secret = match passphrase {
+        Some(p) => p.as_bytes().to_vec(),
+        None => device_key()?,
+    };
+
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(&secret, salt, rounds, &mut key);
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Encrypts `plain`, with `passphrase` if there is one.
+pub fn seal(plain: &str, passphrase: Option<&str>) -> Result<Sealed, Error> {
+    let rounds = match passphrase {
+        Some(_) => PASSPHRASE_ROUNDS,
+        None => DEVICE_ROUNDS,
+    };
+
+    let salt = random::<16>()?;
+    let nonce = random::<12>()?;
+
+    let ciphertext = cipher(passphrase, &salt, rounds)?
+        .encrypt(Nonce::from_slice(&nonce), plain.as_bytes())
+        // Encrypting into a `Vec` can't run out of room.
+        .expect("encryption into a Vec succeeds");
+
+    Ok(Sealed {
+        version: VERSION,
+        passphrase: passphrase.is_some(),
+        rounds,
+        salt: STANDARD.encode(salt),
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+    })
+}
+
+/// Decrypts `sealed`. `passphrase` is ignored if it wasn't sealed with one.
+pub fn open(sealed: &Sealed, passphrase: Option<&str>) -> Result<String, Error> {
+    ensure!(
+        sealed.version == VERSION,
+        VersionSnafu {
+            version: sealed.version
+        }
+    );
+
+    let passphrase = match (sealed.passphrase, passphrase) {
+        (false, _) => None,
+        (true, Some(p)) => Some(p),
+        (true, None) => return NeedPassphraseSnafu.fail(),
+    };
+
+    let salt = STANDARD.decode(&sealed.salt).context(DamagedSnafu)?;
+    let nonce = STANDARD.decode(&sealed.nonce).context(DamagedSnafu)?;
+    let ciphertext = STANDARD.decode(&sealed.ciphertext).context(DamagedSnafu)?;
+
+    ensure!(nonce.len() == 12, WrongSnafu);
+
+    let plain = cipher(passphrase, &salt, sealed.rounds)?
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .ok()
+        .and_then(|p| String::from_utf8(p).ok());
+
+    match plain {
+        Some(p) => Ok(p),
+        None => WrongSnafu.fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_with_passphrase() {
+        let sealed = seal("abc123", Some("hunter2")).unwrap();
+        assert!(sealed.passphrase);
+        assert_ne!(sealed.ciphertext, "abc123");
+        assert_eq!(open(&sealed, Some("hunter2")).unwrap(), "abc123");
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let sealed = seal("abc123", Some("hunter2")).unwrap();
+        assert!(matches!(open(&sealed, Some("hunter3")), Err(Error::Wrong)));
+        assert!(matches!(open(&sealed, None), Err(Error::NeedPassphrase)));
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut sealed = seal("abc123", Some("hunter2")).unwrap();
+        sealed.version = VERSION + 1;
+        assert!(matches!(
+            open(&sealed, Some("hunter2")),
+            Err(Error::Version { .. })
+        ));
+    }
+
+    #[test]
+    fn plain_keys_are_read_as_plain() {
+        let stored: Stored = serde_json::from_str(r#""abc123""#).unwrap();
+        assert_eq!(stored, Stored::Plain("abc123".to_owned()));
+    }
+
+    #[test]
+    fn sealed_keys_are_read_as_sealed() {
+        let sealed = seal("abc123", Some("hunter2")).unwrap();
+        let json = serde_json::to_string(&Stored::Sealed(sealed.clone())).unwrap();
+
+        let stored: Stored = serde_json::from_str(&json).unwrap();
+        assert_eq!(stored, Stored::Sealed(sealed));
+    }
+}
@@ -24,8 +24,14 @@ use std::{
 };
 
 use dioxus::prelude::*;
+use futures::future::{self, AbortHandle};
+use keyboard_types::Key;
 
-use crate::{timers, yiff::Yiff};
+use crate::{
+    app::ResultExt,
+    query, timers,
+    yiff::{Tag, TagCategory, Yiff},
+};
 
 static ID: AtomicUsize = AtomicUsize::new(0);
 
@@ -40,6 +46,20 @@ impl Entries {
             map
         }))
     }
+
+    /// The tags entered so far, in the order they were added.
+    pub fn terms(&self) -> Vec<String> {
+        let entries = self.0.peek();
+        let mut ids: Vec<_> = entries.keys().copied().collect();
+        ids.sort_unstable();
+
+        ids.into_iter()
+            .filter_map(|id| match entries[&id].trim() {
+                "" => None,
+                x => Some(x.to_owned()),
+            })
+            .collect()
+    }
 }
 
 #[component]
@@ -67,10 +87,23 @@ pub fn List(
                             entries.remove(&id);
                         }
                     },
-                    onchange: move |e| {
+                    onchange: move |e: Vec<String>| {
                         let mut entries = entries.write();
-                        *entries.get_mut(&id).unwrap() = e;
+                        let mut parts = e.into_iter();
+                        *entries.get_mut(&id).unwrap() = parts.next().unwrap_or_default();
+
+                        // Anything pasted in alongside the first term gets an
+                        // entry of its own, before the blank one on the end.
                         let mut rightmost = *entries.keys().max().unwrap();
+                        let mut parts = parts.peekable();
+                        if parts.peek().is_some() && rightmost != id && entries[&rightmost].is_empty() {
+                            entries.remove(&rightmost);
+                        }
+                        for part in parts {
+                            entries.insert(ID.fetch_add(1, Ordering::SeqCst), part);
+                        }
+
+                        rightmost = *entries.keys().max().unwrap();
                         if !entries[&rightmost].is_empty() {
                             rightmost = ID.fetch_add(1, Ordering::SeqCst);
                             entries.insert(rightmost, String::new());
@@ -90,6 +123,8 @@ pub fn List(
         .collect::<Vec<_>>();
     children.sort_by_key(|(id, _)| *id);
 
+    let invalid = entries_value.values().any(|e| query::parse(e).is_err());
+
     let search_style = include_str!("tag.css");
     return rsx! {
         style { "{search_style}" }
@@ -97,32 +132,88 @@ pub fn List(
             "Tags:"
             { children.into_iter().map(|(_, e)| e) }
         }
+        Related { yiff, entries }
         button {
             r#type: "button",
             class: "tag-submit",
-            onclick: move |_| {
-                let tags: Vec<_> = entries
-                    .peek()
-                    .values()
-                    .filter_map(|x| {
-                        match x.trim() {
-                            "" => None,
-                            x => Some(x.to_owned()),
-                        }
-                    })
-                    .collect();
-                onsubmit.call(tags);
-            },
+            disabled: invalid,
+            title: if invalid { "Fix the tags marked in red first" } else { "" },
+            onclick: move |_| onsubmit.call(Entries(entries).terms()),
             "Show me the Yiff!"
         }
     };
 }
 
+/// Tags that go well with the ones already entered.
+#[component]
+fn Related(yiff: ReadOnlySignal<Yiff>, entries: Signal<HashMap<usize, String>>) -> Element {
+    let related = use_resource(move || async move {
+        // Only plain tags say anything about what else might be wanted.
+        let tags: Vec<String> = entries
+            .read()
+            .values()
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty() && !t.contains(':'))
+            .filter(|t| !t.starts_with(['-', '~']))
+            .map(str::to_owned)
+            .collect();
+
+        if tags.is_empty() {
+            return Vec::new();
+        }
+
+        yiff.read()
+            .related_tags(tags)
+            .await
+            .record_err()
+            .unwrap_or_default()
+    });
+
+    let mut add = move |tag: String| {
+        let mut entries = entries.write();
+        if entries.values().any(|t| t.trim() == tag) {
+            return;
+        }
+
+        // Replace the blank entry on the end, so the new tag comes before it.
+        let rightmost = *entries.keys().max().unwrap();
+        if entries[&rightmost].is_empty() {
+            entries.remove(&rightmost);
+        }
+        entries.insert(ID.fetch_add(1, Ordering::SeqCst), tag);
+        entries.insert(ID.fetch_add(1, Ordering::SeqCst), String::new());
+    };
+
+    let suggestions = related.read();
+    let suggestions = match suggestions.as_deref() {
+        Some(s) if !s.is_empty() => s,
+        _ => return None,
+    };
+
+    rsx! {
+        div { class: "tag-related",
+            "Related:"
+            for tag in suggestions.iter().take(20) {
+                button {
+                    key: "{tag}",
+                    r#type: "button",
+                    class: "tag-suggestion",
+                    onclick: {
+                        let tag = tag.clone();
+                        move |_| add(tag.clone())
+                    },
+                    "{tag}"
+                }
+            }
+        }
+    }
+}
+
 #[component]
 fn Entry(
     yiff: ReadOnlySignal<Yiff>,
     onremove: EventHandler,
-    onchange: EventHandler<String>,
+    onchange: EventHandler<Vec<String>>,
     id: usize,
     entries: Signal<HashMap<usize, String>>,
 ) -> Element {
@@ -156,16 +247,51 @@ fn Remove(onremove: EventHandler) -> Element {
     }
 }
 
+/// Something offered while typing into an [`Edit`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Suggestion {
+    Tag(Tag),
+    Meta(query::Completion),
+}
+
+impl Suggestion {
+    /// What goes in the box when this is picked.
+    fn text(&self) -> &str {
+        match self {
+            Suggestion::Tag(tag) => &tag.name,
+            Suggestion::Meta(completion) => &completion.text,
+        }
+    }
+
+    /// Whether there's more to type after picking this.
+    fn partial(&self) -> bool {
+        match self {
+            Suggestion::Tag(_) => false,
+            Suggestion::Meta(completion) => completion.partial,
+        }
+    }
+}
+
 #[component]
 fn Edit(
     yiff: ReadOnlySignal<Yiff>,
     value: Signal<String>,
     onremove: EventHandler,
-    onchange: EventHandler<String>,
+    onchange: EventHandler<Vec<String>>,
     id: usize,
 ) -> Element {
-    let mut autocomplete_timer = use_signal(|| Option::<timers::Cancel>::None);
-    let mut autocomplete_suggestions = use_signal(Vec::<String>::new);
+    let mut autocomplete_task = use_signal(|| Option::<AbortHandle>::None);
+    let mut autocomplete_suggestions = use_signal(Vec::<Suggestion>::new);
+    let mut negated = use_signal(|| false);
+    let mut highlighted = use_signal(|| Option::<usize>::None);
+
+    let mut dismiss = move || {
+        if let Some(task) = autocomplete_task.write().take() {
+            task.abort();
+        }
+        autocomplete_suggestions.write().clear();
+        highlighted.set(None);
+    };
 
     let mut chosen = move || {
         use_future(move || {
@@ -174,75 +300,194 @@ fn Edit(
             ))
             .join()
         });
+        dismiss();
 
-        let text = value.peek();
-        if text.is_empty() {
-            autocomplete_suggestions.write().clear();
+        let text = value.peek().clone();
+        if text.trim().is_empty() {
             onremove.call(());
-        } else {
-            onchange.call(text.to_owned());
+            return;
         }
-    };
 
-    let oninput = move |e: Event<FormData>| {
-        let mut cancel = autocomplete_timer.write();
-        if let Some(cancel) = cancel.take() {
-            cancel.cancel();
+        // Tidy up what was typed, splitting it up if there's more than one
+        // term. Invalid input is kept as-is, so it can be fixed.
+        match query::parse(&text) {
+            Ok(parsed) => {
+                let terms = parsed.terms();
+                if let Some(first) = terms.first() {
+                    value.set(first.clone());
+                }
+                onchange.call(terms);
+            }
+            Err(_) => onchange.call(vec![text]),
         }
-        *value.write() = e.value();
+    };
 
-        let text = e.value();
-        if text.len() < 3 {
-            return;
+    let mut suggest = move |text: String| {
+        // Whatever was being looked up is out of date now, so stop waiting
+        // for it rather than letting it replace newer suggestions.
+        if let Some(task) = autocomplete_task.write().take() {
+            task.abort();
         }
 
-        let (new_cancel, future) = timers::cancelable(Duration::from_millis(400));
-        *cancel = Some(new_cancel);
+        let (negate, term) = match text.trim_start().strip_prefix('-') {
+            Some(term) => (true, term.trim().to_owned()),
+            None => (false, text.trim().to_owned()),
+        };
 
-        spawn_forever(async move {
-            if future.await.is_err() {
-                return;
-            }
+        // Metatags are known ahead of time, so they're suggested straight
+        // away, and ordinary tags get added once they've been looked up.
+        let local: Vec<_> = query::complete(&term)
+            .into_iter()
+            .map(Suggestion::Meta)
+            .collect();
+        autocomplete_suggestions.set(local.clone());
+        negated.set(negate);
+        highlighted.set(None);
 
-            let peeked = value.peek();
-            let (negate, text) = match peeked.trim_start().chars().next() {
-                None => return,
-                Some('-') => (true, text.trim_start()[1..].to_owned()),
-                Some(_) => (false, text.to_owned()),
-            };
-            drop(peeked);
+        if term.len() < 3 || query::is_meta(&term) {
+            return;
+        }
 
-            match yiff.read().tags(text).await {
-                Err(e) => println!("{}", e),
+        let (lookup, handle) = future::abortable(async move {
+            timers::cancelable(Duration::from_millis(400)).1.await.ok();
+
+            match yiff.read().tags(term).await {
+                Err(e) => {
+                    Err::<(), _>(e).record_err();
+                }
                 Ok(tags) => {
                     if let Ok(mut suggestions) = autocomplete_suggestions.try_write() {
-                        if negate {
-                            *suggestions = tags.into_iter().map(|x| format!("-{}", x)).collect();
-                        } else {
-                            *suggestions = tags;
-                        }
+                        *suggestions = local
+                            .into_iter()
+                            .chain(tags.into_iter().map(Suggestion::Tag))
+                            .collect();
                     }
                 }
             }
         });
+        autocomplete_task.set(Some(handle));
+
+        spawn_forever(async move {
+            lookup.await.ok();
+        });
+    };
+
+    let mut pick = move |index: usize| {
+        let suggestion = match autocomplete_suggestions.peek().get(index) {
+            Some(suggestion) => suggestion.clone(),
+            None => return,
+        };
+        let text = match *negated.peek() {
+            true => format!("-{}", suggestion.text()),
+            false => suggestion.text().to_owned(),
+        };
+        value.set(text.clone());
+
+        match suggestion.partial() {
+            true => suggest(text),
+            false => chosen(),
+        }
     };
 
+    let problem = query::parse(&value.read()).err().map(|e| e.to_string());
+
+    let oninput = move |e: Event<FormData>| {
+        *value.write() = e.value();
+        suggest(e.value());
+    };
+
+    let onkeydown = move |e: Event<KeyboardData>| {
+        let count = autocomplete_suggestions.peek().len();
+        let current = *highlighted.peek();
+        match e.key() {
+            Key::ArrowDown if count > 0 => {
+                highlighted.set(Some(current.map_or(0, |i| (i + 1) % count)));
+            }
+            Key::ArrowUp if count > 0 => {
+                highlighted.set(Some(current.map_or(count - 1, |i| (i + count - 1) % count)));
+            }
+            Key::Enter => match current {
+                Some(index) => pick(index),
+                None => chosen(),
+            },
+            Key::Escape => dismiss(),
+            _ => (),
+        }
+    };
+
+    let prefix = if negated() { "-" } else { "" };
+    let suggestions = autocomplete_suggestions.read();
+    let open = !suggestions.is_empty();
+
+    // Enter and leaving the box both finish the entry, so there's no
+    // `onchange`: it would also fire after picking `rating:` with Enter.
     rsx! {
         input {
             r#type: "text",
             id: "tag-edit-{id}",
-            class: "tag-edit",
-            list: "tag-edit-list-{id}",
+            class: if problem.is_some() { "tag-edit invalid" } else { "tag-edit" },
+            title: problem.unwrap_or_default(),
+            autocomplete: "off",
+            role: "combobox",
+            "aria-expanded": "{open}",
+            "aria-controls": "tag-edit-list-{id}",
             value: "{value}",
             oninput,
-            onchange: move |_| chosen(),
+            onkeydown,
             onblur: move |_| chosen()
         }
-        datalist { id: "tag-edit-list-{id}",
+        if open {
+            ul { id: "tag-edit-list-{id}", class: "tag-autocomplete", role: "listbox",
+                for (index, suggestion) in suggestions.iter().enumerate() {
+                    li {
+                        key: "{suggestion:?}",
+                        role: "option",
+                        class: if highlighted() == Some(index) { "highlighted" } else { "" },
+                        "aria-selected": "{highlighted() == Some(index)}",
+                        // Picking on mouse down, and not letting the input
+                        // lose focus first, keeps the list from vanishing.
+                        prevent_default: "onmousedown",
+                        onmousedown: move |_| pick(index),
+                        onmouseenter: move |_| highlighted.set(Some(index)),
 
-            for suggestion in &*autocomplete_suggestions.read() {
-                option { "{suggestion}" }
+                        match suggestion {
+                            Suggestion::Tag(tag) => rsx! {
+                                span {
+                                    class: "tag-category",
+                                    title: tag.category.name(),
+                                    style: "background: {colour(tag.category)};"
+                                }
+                                span { class: "tag-name",
+                                    if let Some(alias) = &tag.alias {
+                                        span { class: "tag-alias", "{prefix}{alias} → " }
+                                    }
+                                    "{prefix}{tag.name}"
+                                }
+                                span { class: "tag-count", "{tag.post_count}" }
+                            },
+                            Suggestion::Meta(completion) => rsx! {
+                                span { class: "tag-category meta" }
+                                span { class: "tag-name", "{prefix}{completion.text}" }
+                                span { class: "tag-count", "{completion.about}" }
+                            },
+                        }
+                    }
+                }
             }
         }
     }
 }
+
+/// Colour e621 uses for tags in `category`.
+pub fn colour(category: TagCategory) -> &'static str {
+    match category {
+        TagCategory::Artist => "#f2ac08",
+        TagCategory::Copyright => "#dd00dd",
+        TagCategory::Character => "#00aa00",
+        TagCategory::Species => "#ed5d1f",
+        TagCategory::General => "#b4c7d9",
+        TagCategory::Lore => "#228822",
+        TagCategory::Meta => "#ffffff",
+        TagCategory::Invalid => "#ff3d3d",
+    }
+}
@@ -20,6 +20,17 @@ use crate::app::app;
 
 use std::future::Future;
 
+/// Posts on either side of the current one kept in memory while swiping.
+pub(crate) const HISTORY_LIMIT: usize = 20;
+
+pub(crate) type BoxFuture<'a, T> = futures::future::LocalBoxFuture<'a, T>;
+pub(crate) type BoxStream<'a, T> = futures::stream::LocalBoxStream<'a, T>;
+
+/// Bound for values that have to cross into [`spawn`]ed tasks.
+pub(crate) trait MaybeSend {}
+
+impl<T> MaybeSend for T {}
+
 pub(crate) fn spawn<F>(fut: F)
 where
     F: Future<Output = ()> + 'static,
@@ -27,6 +38,11 @@ where
     wasm_bindgen_futures::spawn_local(fut);
 }
 
+/// A fresh seed for `randseed:`.
+pub(crate) fn random_seed() -> u64 {
+    (js_sys::Math::random() * u32::MAX as f64) as u64
+}
+
 pub(super) fn main() {
     dioxus::launch(app);
 }
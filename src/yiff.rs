@@ -16,21 +16,29 @@
  * You should have received a copy of the GNU Affero General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-use crate::platform::spawn;
+#[cfg_attr(feature = "offline", allow(dead_code))]
+pub mod e621;
+#[cfg(feature = "offline")]
+pub mod memory;
+
+use crate::blacklist::Blacklist;
+use crate::platform::{spawn, BoxFuture, BoxStream, MaybeSend};
 
 use futures::channel::oneshot::Canceled;
 use futures::channel::{mpsc, oneshot};
-use futures::stream::StreamExt;
-use futures::{SinkExt, TryStreamExt};
+use futures::future::{self, Either};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use futures::SinkExt;
+
+use rs621::post::{Post, VoteDir};
 
-use rs621::client::Client;
-use rs621::post::{Post, Query, VoteDir, VoteMethod};
-use rs621::tag;
+use serde::{Deserialize, Serialize};
 
-use snafu::{Backtrace, IntoError, ResultExt, Snafu};
+use snafu::{Backtrace, IntoError, OptionExt, Snafu};
 
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -40,86 +48,768 @@ pub enum Error {
         action: String,
         backtrace: Backtrace,
     },
+    #[snafu(display("While trying to {action}, encountered: {source}"))]
+    Http {
+        source: reqwest::Error,
+        action: String,
+        backtrace: Backtrace,
+    },
     #[snafu(display("While waiting for {action}, encountered: {source}"))]
     NoReply {
         source: Canceled,
         action: String,
         backtrace: Backtrace,
     },
+    #[snafu(display("Post #{post_id} no longer exists"))]
+    Missing { post_id: u64, backtrace: Backtrace },
+    #[snafu(display("There's no account named \"{username}\""))]
+    NoAccount {
+        username: String,
+        backtrace: Backtrace,
+    },
+}
+
+/// Broad reason an [`Error`] happened, for explaining it to the user.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Problem {
+    /// The username or API key was rejected (HTTP 401 or 403.)
+    ///
+    /// Everything this app asks for is either public or the user's own
+    /// (sets come from `search[creator_name]`), so with working credentials
+    /// there's nothing left to be forbidden from.
+    Unauthorized,
+    /// Whatever was asked for doesn't exist (HTTP 404.)
+    NotFound,
+    /// Too many requests, too quickly.
+    RateLimited,
+    /// The booru couldn't be reached at all.
+    Network,
+    Other,
+}
+
+impl Problem {
+    fn from_status(code: u16) -> Self {
+        match code {
+            401 | 403 => Problem::Unauthorized,
+            404 => Problem::NotFound,
+            // e621 answers 503 when it's rate limiting.
+            429 | 503 => Problem::RateLimited,
+            _ => Problem::Other,
+        }
+    }
+}
+
+impl Error {
+    pub fn problem(&self) -> Problem {
+        match self {
+            Error::Api { source, .. } => match source {
+                rs621::error::Error::Http { code, .. } => Problem::from_status(*code),
+                rs621::error::Error::CannotSendRequest(_) => Problem::Network,
+                _ => Problem::Other,
+            },
+            Error::Http { source, .. } => match source.status() {
+                Some(status) => Problem::from_status(status.as_u16()),
+                None if source.is_request() || source.is_timeout() => Problem::Network,
+                None => Problem::Other,
+            },
+            Error::Missing { .. } | Error::NoAccount { .. } => Problem::NotFound,
+            Error::NoReply { .. } => Problem::Other,
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct Yiff {
-    client: Arc<Client>,
+/// A booru that can be searched and voted on.
+///
+/// Everything above [`Yiff`] talks to the booru through this trait, so the
+/// UI can run against [`e621::E621`] or, with the `offline` feature, against
+/// the fixtures in [`memory::Memory`].
+pub trait Backend: MaybeSend + 'static {
+    /// Every post matching `terms`, in the order the booru returns them.
+    fn post_search(&self, terms: Vec<String>) -> BoxStream<'_, Result<Post, Error>>;
+
+    /// The post with the given id, or `None` if it doesn't exist.
+    fn post(&self, post_id: u64) -> BoxFuture<'_, Result<Option<Post>, Error>>;
+
+    fn favorite(&self, post_id: u64) -> BoxFuture<'_, Result<(), Error>>;
+
+    fn unfavorite(&self, post_id: u64) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Replace any existing vote on `post_id` with one in direction `dir`.
+    fn vote(&self, post_id: u64, dir: VoteDir) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// One page of posts matching `terms`, up to [`PAGE_SIZE`] of them.
+    fn post_page(&self, terms: Vec<String>, page: Page) -> BoxFuture<'_, Result<Vec<Post>, Error>>;
+
+    /// Up to [`TAG_LIMIT`] tags starting with `needle`, including tags that `needle` is
+    /// an alias of.
+    fn tags(&self, needle: String) -> BoxFuture<'_, Result<Vec<Tag>, Error>>;
+
+    /// Names of tags that often show up on posts together with all of
+    /// `tags`, most common first.
+    fn related_tags(&self, tags: Vec<String>) -> BoxFuture<'_, Result<Vec<String>, Error>>;
+
+    /// The logged in account's blacklist, in e621's line-based syntax.
+    fn blacklist(&self) -> BoxFuture<'_, Result<String, Error>>;
+
+    /// Makes an authenticated request, to find out if the credentials work.
+    fn check_login(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// The pool with the given id, or `None` if it doesn't exist.
+    fn pool(&self, pool_id: u64) -> BoxFuture<'_, Result<Option<Pool>, Error>>;
+
+    /// Up to 30 pools with names matching `name`.
+    fn pool_search(&self, name: String) -> BoxFuture<'_, Result<Vec<Pool>, Error>>;
+
+    /// The post sets owned by the logged in account.
+    fn post_sets(&self) -> BoxFuture<'_, Result<Vec<PostSet>, Error>>;
+
+    fn set_add(&self, set_id: u64, post_id: u64) -> BoxFuture<'_, Result<(), Error>>;
+
+    fn set_remove(&self, set_id: u64, post_id: u64) -> BoxFuture<'_, Result<(), Error>>;
 }
 
-impl Yiff {
+#[cfg(not(feature = "offline"))]
+pub type DefaultBackend = e621::E621;
+
+#[cfg(feature = "offline")]
+pub type DefaultBackend = memory::Memory;
+
+/// Returns every tag on `post`, regardless of category.
+pub fn post_tags(post: &Post) -> impl Iterator<Item = &str> {
+    let tags = &post.tags;
+    tags.artist
+        .iter()
+        .chain(&tags.copyright)
+        .chain(&tags.character)
+        .chain(&tags.species)
+        .chain(&tags.general)
+        .chain(&tags.lore)
+        .chain(&tags.meta)
+        .chain(&tags.invalid)
+        .map(String::as_str)
+}
+
+/// Kinds of tag, numbered the way e621 numbers them.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(from = "u8")]
+pub enum TagCategory {
+    General,
+    Artist,
+    Copyright,
+    Character,
+    Species,
+    Invalid,
+    Meta,
+    Lore,
+}
+
+impl From<u8> for TagCategory {
+    fn from(id: u8) -> Self {
+        match id {
+            1 => TagCategory::Artist,
+            3 => TagCategory::Copyright,
+            4 => TagCategory::Character,
+            5 => TagCategory::Species,
+            6 => TagCategory::Invalid,
+            7 => TagCategory::Meta,
+            8 => TagCategory::Lore,
+            _ => TagCategory::General,
+        }
+    }
+}
+
+impl TagCategory {
+    pub fn name(self) -> &'static str {
+        match self {
+            TagCategory::General => "general",
+            TagCategory::Artist => "artist",
+            TagCategory::Copyright => "copyright",
+            TagCategory::Character => "character",
+            TagCategory::Species => "species",
+            TagCategory::Invalid => "invalid",
+            TagCategory::Meta => "meta",
+            TagCategory::Lore => "lore",
+        }
+    }
+}
+
+/// A tag offered while typing.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub category: TagCategory,
+    pub post_count: u64,
+    /// What was typed, when it's an alias that resolves to `name`.
+    pub alias: Option<String>,
+}
+
+/// How many posts [`Backend::post_page`] asks for at once.
+pub const PAGE_SIZE: usize = 75;
+
+/// Which page of results to fetch.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Page {
+    /// Counting from 1.
+    Number(u64),
+    /// Posts with ids lower than this one (`page=b<id>`.)
+    Before(u64),
+    /// Posts with ids higher than this one (`page=a<id>`.)
+    After(u64),
+    /// Posts sorted after one whose `key` (like `score`) was `value`, with
+    /// ties broken by id, highest first. The booru has no cursor for these,
+    /// so it's sent as search terms instead.
+    Following {
+        key: &'static str,
+        value: i64,
+        id: u64,
+    },
+}
+
+/// How search results are sorted.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Order {
+    #[default]
+    Random,
+    Newest,
+    Oldest,
+    Score,
+    Favcount,
+}
+
+impl Order {
+    pub const ALL: [Order; 5] = [
+        Order::Random,
+        Order::Newest,
+        Order::Oldest,
+        Order::Score,
+        Order::Favcount,
+    ];
+
+    /// The `order:` metatag for this order.
+    pub fn term(self) -> &'static str {
+        match self {
+            Order::Random => "order:random",
+            Order::Newest => "order:id_desc",
+            Order::Oldest => "order:id",
+            Order::Score => "order:score",
+            Order::Favcount => "order:favcount",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::Random => "Random",
+            Order::Newest => "Newest",
+            Order::Oldest => "Oldest",
+            Order::Score => "Top score",
+            Order::Favcount => "Most favorited",
+        }
+    }
+
+    pub fn from_term(term: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|o| o.term() == term)
+    }
+
+    /// Where a search picks up after the posts in `snapshot`.
+    ///
+    /// Only ids are kept, so orders other than by id start from the top
+    /// again, and the posts already seen are dropped as duplicates.
+    fn first_page(self, snapshot: &Snapshot) -> Page {
+        let ids = snapshot.seen.iter().map(|(id, _)| *id);
+        let cursor = match self {
+            Order::Newest => ids.min().map(Page::Before),
+            Order::Oldest => ids.max().map(Page::After),
+            _ => None,
+        };
+        cursor.unwrap_or(Page::Number(1))
+    }
+
+    /// Where the page after one ending with `last` starts.
+    ///
+    /// Each page starts from the last post of the one before, rather than
+    /// from a page number. Voting on a post with `-voted:anything` in the
+    /// search takes it out of the results, and numbered pages would then
+    /// skip as many posts as were voted on.
+    fn next_page(self, current: Page, last: &Post) -> Page {
+        match (self, current) {
+            (Order::Newest, _) => Page::Before(last.id),
+            (Order::Oldest, _) => Page::After(last.id),
+            (Order::Score, _) => Page::Following {
+                key: "score",
+                value: last.score.total,
+                id: last.id,
+            },
+            (Order::Favcount, _) => Page::Following {
+                key: "favcount",
+                value: last.fav_count as i64,
+                id: last.id,
+            },
+            (Order::Random, Page::Number(n)) => Page::Number(n + 1),
+            (Order::Random, page) => page,
+        }
+    }
+}
+
+/// A set of posts, like the pages of a comic, that go in a particular order.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Pool {
+    pub id: u64,
+    pub name: String,
+    pub post_ids: Vec<u64>,
+}
+
+impl Pool {
+    /// The name as shown on the site, which uses underscores for spaces.
+    pub fn title(&self) -> String {
+        self.name.replace('_', " ")
+    }
+}
+
+/// A user's own collection of posts, in no particular order.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PostSet {
+    pub id: u64,
+    pub name: String,
+    pub post_ids: Vec<u64>,
+}
+
+/// Where a [`Search`] gets its posts from.
+enum Source {
+    /// Paged through by the backend, for `order:random`.
+    Terms(Vec<String>),
+    /// Paged through here, so each page picks up where the last ended.
+    Ordered(Vec<String>, Order, Page),
+    Pool(Pool),
+}
+
+/// The posts matching `terms`, sorted by `order`, one page at a time.
+fn ordered_posts<B: Backend>(
+    backend: &B,
+    terms: Vec<String>,
+    order: Order,
+    first: Page,
+) -> BoxStream<'_, Result<Post, Error>> {
+    let pages = stream::try_unfold(Some(first), move |page| {
+        let terms = terms.clone();
+        async move {
+            let page = match page {
+                Some(p) => p,
+                None => return Ok(None),
+            };
+
+            let posts = backend.post_page(terms, page).await?;
+            let next = match posts.last() {
+                Some(last) if posts.len() >= PAGE_SIZE => Some(order.next_page(page, last)),
+                _ => None,
+            };
+
+            Ok(Some((stream::iter(posts.into_iter().map(Ok)), next)))
+        }
+    });
+
+    Box::pin(pages.try_flatten())
+}
+
+/// The posts in `pool`, in the pool's order instead of the booru's, asked
+/// for by id a page at a time.
+fn pool_posts<B: Backend>(backend: &B, pool: Pool) -> BoxStream<'_, Result<Post, Error>> {
+    let chunks: Vec<Vec<u64>> = pool
+        .post_ids
+        .chunks(PAGE_SIZE)
+        .map(<[u64]>::to_vec)
+        .collect();
+
+    let pages = stream::iter(chunks).then(move |ids| async move {
+        let list: Vec<String> = ids.iter().map(u64::to_string).collect();
+        let terms = vec![format!("id:{}", list.join(","))];
+
+        let mut posts: HashMap<u64, Post> = backend
+            .post_page(terms, Page::Number(1))
+            .await?
+            .into_iter()
+            .map(|p| (p.id, p))
+            .collect();
+
+        // Pages that were deleted (or are hidden) just don't come up.
+        let ordered = ids.into_iter().filter_map(move |id| posts.remove(&id));
+        Ok::<_, Error>(stream::iter(ordered.map(Ok)))
+    });
+
+    Box::pin(pages.try_flatten())
+}
+
+/// How many posts a [`Search`] remembers at all, even just by id. Older ones
+/// are forgotten entirely, and can't be rewound to.
+const SEEN_LIMIT: usize = 5000;
+
+/// How many posts a [`Search`] fetches before they're needed.
+const DEFAULT_LOOKAHEAD: usize = 1;
+
+/// How many tags [`Backend::tags`] finds at most. e621's autocomplete never
+/// sends more than this, so any fewer means there weren't any more to find.
+pub const TAG_LIMIT: usize = 10;
+
+/// How many [`Yiff::tags`] lookups are remembered.
+const TAG_CACHE_SIZE: usize = 64;
+
+/// How many posts' tags are kept for [`Yiff::related_tags`] to fall back on.
+const RECENT_LIMIT: usize = 100;
+
+/// Tags of the posts searches have handed out lately, oldest first.
+#[derive(Debug, Default)]
+struct Recent(VecDeque<Vec<String>>);
+
+impl Recent {
+    fn push(&mut self, post: &Post) {
+        if self.0.len() >= RECENT_LIMIT {
+            self.0.pop_front();
+        }
+        self.0
+            .push_back(post_tags(post).map(str::to_owned).collect());
+    }
+}
+
+/// Tags that show up most often on the posts (given as their tags) that
+/// have every one of `tags`, most common first.
+pub(crate) fn co_occurring<'a, P>(posts: P, tags: &[String]) -> Vec<String>
+where
+    P: IntoIterator<Item = Vec<&'a str>>,
+{
+    let mut counts = HashMap::<&str, usize>::new();
+    let matching = posts
+        .into_iter()
+        .filter(|post| tags.iter().all(|t| post.contains(&t.as_str())));
+
+    for post in matching {
+        for tag in post {
+            if !tags.iter().any(|t| t == tag) {
+                *counts.entry(tag).or_default() += 1;
+            }
+        }
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    counts
+        .into_iter()
+        .take(30)
+        .map(|(name, _)| name.to_owned())
+        .collect()
+}
+
+/// Recent [`Yiff::tags`] lookups, least recently used first.
+#[derive(Debug, Default)]
+struct TagCache(VecDeque<(String, Vec<Tag>)>);
+
+impl TagCache {
+    fn get(&mut self, needle: &str) -> Option<Vec<Tag>> {
+        if let Some(index) = self.0.iter().position(|(n, _)| n == needle) {
+            let entry = self.0.remove(index)?;
+            let tags = entry.1.clone();
+            self.0.push_back(entry);
+            return Some(tags);
+        }
+
+        // A lookup that found fewer than the limit found everything starting
+        // with its needle, so it also has everything starting with this one.
+        let (_, tags) = self
+            .0
+            .iter()
+            .rev()
+            .filter(|(n, tags)| tags.len() < TAG_LIMIT && needle.starts_with(n.as_str()))
+            .max_by_key(|(n, _)| n.len())?;
+
+        let tags: Vec<_> = tags
+            .iter()
+            .filter(|t| t.alias.as_deref().unwrap_or(&t.name).starts_with(needle))
+            .cloned()
+            .collect();
+        self.put(needle.to_owned(), tags.clone());
+        Some(tags)
+    }
+
+    fn put(&mut self, needle: String, tags: Vec<Tag>) {
+        self.0.retain(|(n, _)| *n != needle);
+        if self.0.len() >= TAG_CACHE_SIZE {
+            self.0.pop_front();
+        }
+        self.0.push_back((needle, tags));
+    }
+}
+
+#[derive(Debug)]
+pub struct Yiff<B = DefaultBackend> {
+    backend: Arc<B>,
+    history_limit: usize,
+    lookahead: usize,
+    blacklist: Arc<Blacklist>,
+    tag_cache: Arc<Mutex<TagCache>>,
+    recent: Arc<Mutex<Recent>>,
+}
+
+impl<B> Clone for Yiff<B> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            history_limit: self.history_limit,
+            lookahead: self.lookahead,
+            blacklist: self.blacklist.clone(),
+            tag_cache: self.tag_cache.clone(),
+            recent: self.recent.clone(),
+        }
+    }
+}
+
+impl Yiff<e621::E621> {
+    #[cfg_attr(feature = "offline", allow(dead_code))]
     pub fn new<U, K>(base_url: &str, creator: &str, username: U, api_key: K) -> Self
     where
         U: Into<String>,
         K: Into<String>,
     {
-        // TODO: When in a browser, set the `_client` query parameter.
-        let mut client = Client::new(
-            base_url,
-            format!(
-                "{}/{} (by {})",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION"),
-                creator,
-            ),
-        )
-        .unwrap();
-
-        client.login(username.into(), api_key.into());
+        Self::with_backend(e621::E621::new(base_url, creator, username, api_key))
+    }
+}
 
+impl<B> Yiff<B>
+where
+    B: Backend,
+{
+    pub fn with_backend(backend: B) -> Self {
         Self {
-            client: Arc::new(client),
+            backend: Arc::new(backend),
+            history_limit: crate::platform::HISTORY_LIMIT,
+            lookahead: DEFAULT_LOOKAHEAD,
+            blacklist: Arc::default(),
+            tag_cache: Arc::default(),
+            recent: Arc::default(),
         }
     }
 
-    pub fn search<T>(&self, query: T) -> Search
+    /// Sets how many posts on either side of the current one a [`Search`]
+    /// keeps in memory. Posts further away are forgotten, except for their
+    /// id, and fetched again when rewinding.
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    /// Sets how many posts a [`Search`] fetches in the background, before
+    /// they're needed. Fetching ahead by more than what's left of a page
+    /// loads the next page early.
+    pub fn with_lookahead(mut self, lookahead: usize) -> Self {
+        self.lookahead = lookahead;
+        self
+    }
+
+    /// Sets the posts a [`Search`] quietly skips.
+    pub fn with_blacklist(mut self, blacklist: Blacklist) -> Self {
+        self.blacklist = Arc::new(blacklist);
+        self
+    }
+
+    pub fn search<I, S>(&self, terms: I, order: Order) -> Search
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.resume(terms, order, Snapshot::default())
+    }
+
+    /// Like [`Yiff::search`], but starting from a [`Snapshot`] of an earlier
+    /// search with the same `terms` and `order`.
+    ///
+    /// Posts in the snapshot are fetched again as they're needed, and are
+    /// skipped when they come up in the search results.
+    pub fn resume<I, S>(&self, terms: I, order: Order, snapshot: Snapshot) -> Search
     where
-        T: Into<Query>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
     {
-        let query = query.into();
-        let client = self.client.clone();
+        let mut terms: Vec<String> = terms.into_iter().map(Into::into).collect();
+        terms.push(order.term().to_owned());
+
+        let source = match order {
+            Order::Random => Source::Terms(terms),
+            _ => Source::Ordered(terms, order, order.first_page(&snapshot)),
+        };
+        self.start(source, snapshot)
+    }
+
+    /// Walks through the posts in `pool` in order, starting from a
+    /// [`Snapshot`] of an earlier read (or [`Snapshot::default`] for the
+    /// first page.)
+    pub fn read(&self, pool: Pool, snapshot: Snapshot) -> Search {
+        self.start(Source::Pool(pool), snapshot)
+    }
+
+    fn start(&self, source: Source, snapshot: Snapshot) -> Search {
+        let backend = self.backend.clone();
+        let limit = self.history_limit;
         let (sender, mut receiver) = mpsc::channel::<Envelope>(5);
 
+        let resumed: HashSet<u64> = snapshot.seen.iter().map(|(id, _)| *id).collect();
+        let mut seen = HashSet::new();
+        let mut history: VecDeque<Slot> = snapshot
+            .seen
+            .iter()
+            .map(|(post_id, choice)| Slot::Evicted {
+                post_id: *post_id,
+                choice: *choice,
+            })
+            .collect();
+
+        // The viewer advances once to show the first post, so start just
+        // before the post that was current when the snapshot was taken.
+        let mut index = snapshot.index.saturating_sub(1).min(history.len());
+
+        let lookahead = self.lookahead;
+        let shared = Arc::new(Mutex::new(Shared {
+            snapshot,
+            upcoming: Vec::new(),
+        }));
+        let shared_clone = shared.clone();
+
+        let duplicates = Arc::new(AtomicUsize::new(0));
+        let duplicates_clone = duplicates.clone();
+
+        let recent = self.recent.clone();
+
+        let blacklist = self.blacklist.clone();
+        let blacklisted = Arc::new(AtomicUsize::new(0));
+        let blacklisted_clone = blacklisted.clone();
+
         let background = async move {
-            let mut search = client.post_search(query);
+            // Pages can overlap (especially with `order:random`), so drop any
+            // post that has already come up in this session.
+            let posts = match source {
+                Source::Terms(terms) => backend.post_search(terms),
+                Source::Ordered(terms, order, first) => {
+                    ordered_posts(&*backend, terms, order, first)
+                }
+                Source::Pool(pool) => pool_posts(&*backend, pool),
+            };
 
-            let mut history = VecDeque::<Arc<Post>>::new();
-            let mut index = 0;
+            let mut search = posts.try_filter(move |p| {
+                // Posts from the snapshot are already in the history, and
+                // coming across them again isn't a duplicate.
+                if resumed.contains(&p.id) {
+                    return future::ready(false);
+                }
+
+                let fresh = seen.insert(p.id);
+                if !fresh {
+                    duplicates_clone.fetch_add(1, Ordering::Relaxed);
+                    return future::ready(false);
+                }
+
+                let blocked = blacklist.matches(p);
+                if blocked {
+                    blacklisted_clone.fetch_add(1, Ordering::Relaxed);
+                }
+                future::ready(!blocked)
+            });
+
+            // Results fetched before they're asked for. Holding an error stops
+            // fetching until the error has been handed out.
+            let mut ahead = VecDeque::<Result<Arc<Post>, Error>>::new();
+            let mut exhausted = false;
+
+            loop {
+                let stalled = matches!(ahead.back(), Some(Err(_)));
+                let envelope = if exhausted || stalled || ahead.len() >= lookahead {
+                    receiver.next().await
+                } else {
+                    match future::select(receiver.next(), search.next()).await {
+                        Either::Left((envelope, _)) => envelope,
+                        Either::Right((result, _)) => {
+                            match result {
+                                Some(r) => ahead.push_back(r.map(Arc::new)),
+                                None => exhausted = true,
+                            }
+                            shared_clone.lock().unwrap().upcoming =
+                                upcoming(&history, index, &ahead);
+                            continue;
+                        }
+                    }
+                };
+
+                let Envelope { msg, reply } = match envelope {
+                    Some(e) => e,
+                    None => break,
+                };
 
-            while let Some(Envelope { msg, reply }) = receiver.next().await {
                 let post = match msg {
-                    Msg::Rewind if history.is_empty() => None,
-                    Msg::Rewind if index == 0 => None,
-                    Msg::Rewind if index == 1 => {
-                        index -= 1;
+                    Msg::Record(post_id, choice) => {
+                        let slot = history.iter_mut().rev().find(|s| s.post_id() == post_id);
+                        if let Some(slot) = slot {
+                            slot.set_choice(choice);
+                        }
                         None
                     }
+
+                    // Posts deleted since they were seen are dropped from
+                    // the history, rather than stopping the search there.
                     Msg::Rewind => {
-                        index -= 1;
-                        Some(Ok(history[index - 1].clone()))
-                    }
+                        let mut found = None;
+                        while index > 1 {
+                            match history[index - 2].load(&*backend).await {
+                                Err(Error::Missing { .. }) => {
+                                    history.remove(index - 2);
+                                    index -= 1;
+                                }
+                                result => {
+                                    if result.is_ok() {
+                                        index -= 1;
+                                    }
+                                    found = Some(result);
+                                    break;
+                                }
+                            }
+                        }
 
-                    Msg::Peek | Msg::Advance if index < history.len() => {
-                        Some(Ok(history[index].clone()))
+                        if found.is_none() && index == 1 {
+                            index = 0;
+                        }
+                        found
                     }
-                    Msg::Peek | Msg::Advance => match search.next().await {
-                        Some(Ok(p)) => {
-                            // TODO: Limit the max history size.
-                            let post = Arc::new(p);
-                            history.push_back(post.clone());
-                            Some(Ok(post))
+
+                    Msg::Advance => {
+                        let mut found = None;
+                        while index < history.len() {
+                            match history[index].load(&*backend).await {
+                                Err(Error::Missing { .. }) => {
+                                    history.remove(index);
+                                }
+                                result => {
+                                    found = Some(result);
+                                    break;
+                                }
+                            }
                         }
-                        Some(Err(e)) => Some(Err(e).with_context(|_| ApiSnafu {
-                            action: format!("search page {index}"),
-                        })),
-                        None => None,
-                    },
+
+                        match found {
+                            Some(result) => Some(result),
+                            None => match ahead.pop_front() {
+                                Some(n) => Some(n),
+                                None if exhausted => None,
+                                None => {
+                                    let next = search.next().await;
+                                    exhausted = next.is_none();
+                                    next.map(|r| r.map(Arc::new))
+                                }
+                            }
+                            .map(|result| {
+                                result.map(|post| {
+                                    recent.lock().unwrap().push(&post);
+                                    let seen = Seen { post, choice: None };
+                                    history.push_back(Slot::Loaded(seen.clone()));
+                                    seen
+                                })
+                            }),
+                        }
+                    }
                 };
 
                 if msg == Msg::Advance {
@@ -128,6 +818,27 @@ impl Yiff {
                     }
                 }
 
+                for (position, slot) in history.iter_mut().enumerate() {
+                    if position.abs_diff(index) > limit {
+                        slot.evict();
+                    }
+                }
+
+                let forgotten = history
+                    .len()
+                    .saturating_sub(SEEN_LIMIT)
+                    .min(index.saturating_sub(1));
+                history.drain(..forgotten);
+                index -= forgotten;
+
+                *shared_clone.lock().unwrap() = Shared {
+                    snapshot: Snapshot {
+                        seen: history.iter().map(|s| (s.post_id(), s.choice())).collect(),
+                        index,
+                    },
+                    upcoming: upcoming(&history, index, &ahead),
+                };
+
                 if reply.send(post).is_err() {
                     break;
                 }
@@ -136,89 +847,240 @@ impl Yiff {
 
         spawn(background);
 
-        Search { sender }
+        Search {
+            sender,
+            shared,
+            duplicates,
+            blacklisted,
+        }
     }
 
     pub async fn favorite(&self, post_id: u64) -> Result<(), Error> {
-        self.client
-            .post_favorite(post_id)
-            .await
-            .map(|_| ())
-            .with_context(|_| ApiSnafu {
-                action: format!("fav #{post_id}"),
-            })
+        self.backend.favorite(post_id).await
     }
 
     pub async fn unfavorite(&self, post_id: u64) -> Result<(), Error> {
-        self.client
-            .post_unfavorite(post_id)
-            .await
-            .with_context(|_| ApiSnafu {
-                action: format!("unfav #{post_id})"),
-            })
+        self.backend.unfavorite(post_id).await
     }
 
     pub async fn vote_up(&self, post_id: u64) -> Result<(), Error> {
-        self.client
-            .post_vote(post_id, VoteMethod::Set, VoteDir::Up)
-            .await
-            .map(|_| ())
-            .with_context(|_| ApiSnafu {
-                action: format!("vote up #{post_id}"),
-            })
+        self.backend.vote(post_id, VoteDir::Up).await
     }
 
     pub async fn vote_down(&self, post_id: u64) -> Result<(), Error> {
-        self.client
-            .post_vote(post_id, VoteMethod::Set, VoteDir::Down)
-            .await
-            .map(|_| ())
-            .with_context(|_| ApiSnafu {
-                action: format!("vote down #{post_id}"),
-            })
+        self.backend.vote(post_id, VoteDir::Down).await
     }
 
-    pub async fn tags(&self, needle: String) -> Result<Vec<String>, Error> {
-        let query = tag::Query::new()
-            .fuzzy_name_matches(needle.clone())
-            .order(tag::Order::Similarity)
-            .per_page(30);
-
-        let tags = self
-            .client
-            .tag_search(query)
-            .take(30)
-            .map_ok(|t| t.name)
-            .try_collect::<Vec<_>>()
-            .await
-            .with_context(move |_| ApiSnafu {
-                action: format!("find tags matching `{needle}`"),
-            })?;
+    /// Tags starting with `needle`, remembering recent lookups so typing
+    /// doesn't ask for the same tags over and over.
+    pub async fn tags(&self, needle: String) -> Result<Vec<Tag>, Error> {
+        let needle = needle.trim().to_lowercase();
+        if let Some(tags) = self.tag_cache.lock().unwrap().get(&needle) {
+            return Ok(tags);
+        }
 
+        let tags = self.backend.tags(needle.clone()).await?;
+        self.tag_cache.lock().unwrap().put(needle, tags.clone());
         Ok(tags)
     }
+
+    /// Tags that go well with `tags`. When the backend can't say, they're
+    /// counted from the posts searches have shown lately instead.
+    pub async fn related_tags(&self, tags: Vec<String>) -> Result<Vec<String>, Error> {
+        let error = match self.backend.related_tags(tags.clone()).await {
+            Ok(related) => return Ok(related),
+            Err(e) => e,
+        };
+
+        let recent = self.recent.lock().unwrap();
+        let posts = recent
+            .0
+            .iter()
+            .map(|t| t.iter().map(String::as_str).collect());
+        match co_occurring(posts, &tags) {
+            related if related.is_empty() => Err(error),
+            related => Ok(related),
+        }
+    }
+
+    pub async fn blacklist(&self) -> Result<String, Error> {
+        self.backend.blacklist().await
+    }
+
+    pub async fn check_login(&self) -> Result<(), Error> {
+        self.backend.check_login().await
+    }
+
+    pub async fn pool(&self, pool_id: u64) -> Result<Option<Pool>, Error> {
+        self.backend.pool(pool_id).await
+    }
+
+    pub async fn pools(&self, name: String) -> Result<Vec<Pool>, Error> {
+        self.backend.pool_search(name).await
+    }
+
+    pub async fn post_sets(&self) -> Result<Vec<PostSet>, Error> {
+        self.backend.post_sets().await
+    }
+
+    pub async fn add_to_set(&self, set_id: u64, post_id: u64) -> Result<(), Error> {
+        self.backend.set_add(set_id, post_id).await
+    }
+
+    pub async fn remove_from_set(&self, set_id: u64, post_id: u64) -> Result<(), Error> {
+        self.backend.set_remove(set_id, post_id).await
+    }
+}
+
+/// What the user did with a post.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Choice {
+    Favorite,
+    Like,
+    Dislike,
+}
+
+/// A post from a [`Search`], along with what the user chose to do with it.
+#[derive(Debug, Clone)]
+pub struct Seen {
+    pub post: Arc<Post>,
+    pub choice: Option<Choice>,
+}
+
+/// Enough of a [`Search`] to pick it up again with [`Yiff::resume`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Ids of the posts seen so far, oldest first, and what the user chose.
+    pub seen: Vec<(u64, Option<Choice>)>,
+    /// One more than the position of the current post in `seen`.
+    pub index: usize,
+}
+
+impl Snapshot {
+    /// Forgets the oldest posts seen, until there are no more than `limit`
+    /// (or the current post would go.) The newest posts are what the id
+    /// orders pick up from, so they're the ones kept.
+    pub fn truncate(&mut self, limit: usize) {
+        let excess = self
+            .seen
+            .len()
+            .saturating_sub(limit)
+            .min(self.index.saturating_sub(1));
+
+        self.seen.drain(..excess);
+        self.index -= excess;
+    }
+}
+
+/// State a [`Search`] can read without waiting on its background task.
+#[derive(Debug, Default)]
+struct Shared {
+    snapshot: Snapshot,
+    upcoming: Vec<Arc<Post>>,
+}
+
+/// Posts after the current one that are already loaded, in order.
+fn upcoming(
+    history: &VecDeque<Slot>,
+    index: usize,
+    ahead: &VecDeque<Result<Arc<Post>, Error>>,
+) -> Vec<Arc<Post>> {
+    let loaded = history.iter().skip(index).map_while(|s| match s {
+        Slot::Loaded(seen) => Some(seen.post.clone()),
+        Slot::Evicted { .. } => None,
+    });
+
+    let fetched = ahead.iter().map_while(|r| r.as_ref().ok().cloned());
+
+    loaded.chain(fetched).collect()
+}
+
+/// An entry in a [`Search`]'s history.
+#[derive(Debug)]
+enum Slot {
+    Loaded(Seen),
+    /// Too far from the current post to keep around.
+    Evicted {
+        post_id: u64,
+        choice: Option<Choice>,
+    },
+}
+
+impl Slot {
+    fn post_id(&self) -> u64 {
+        match self {
+            Slot::Loaded(seen) => seen.post.id,
+            Slot::Evicted { post_id, .. } => *post_id,
+        }
+    }
+
+    fn choice(&self) -> Option<Choice> {
+        match self {
+            Slot::Loaded(seen) => seen.choice,
+            Slot::Evicted { choice, .. } => *choice,
+        }
+    }
+
+    fn set_choice(&mut self, choice: Choice) {
+        match self {
+            Slot::Loaded(seen) => seen.choice = Some(choice),
+            Slot::Evicted { choice: c, .. } => *c = Some(choice),
+        }
+    }
+
+    fn evict(&mut self) {
+        if let Slot::Loaded(seen) = self {
+            *self = Slot::Evicted {
+                post_id: seen.post.id,
+                choice: seen.choice,
+            };
+        }
+    }
+
+    /// Returns the post, fetching it again if it was evicted.
+    async fn load<B: Backend>(&mut self, backend: &B) -> Result<Seen, Error> {
+        let (post_id, choice) = match self {
+            Slot::Loaded(seen) => return Ok(seen.clone()),
+            Slot::Evicted { post_id, choice } => (*post_id, *choice),
+        };
+
+        let post = backend
+            .post(post_id)
+            .await?
+            .context(MissingSnafu { post_id })?;
+
+        let seen = Seen {
+            post: Arc::new(post),
+            choice,
+        };
+        *self = Slot::Loaded(seen.clone());
+        Ok(seen)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum Msg {
-    Peek,
     Advance,
     Rewind,
+    Record(u64, Choice),
 }
 
 #[derive(Debug)]
 struct Envelope {
     msg: Msg,
-    reply: oneshot::Sender<Option<Result<Arc<Post>, Error>>>,
+    reply: oneshot::Sender<Option<Result<Seen, Error>>>,
 }
 
 #[derive(Debug)]
 pub struct Search {
     sender: mpsc::Sender<Envelope>,
+    shared: Arc<Mutex<Shared>>,
+    duplicates: Arc<AtomicUsize>,
+    blacklisted: Arc<AtomicUsize>,
 }
 
 impl Search {
-    async fn fetch(&mut self, msg: Msg) -> Option<Result<Arc<Post>, Error>> {
+    async fn fetch(&mut self, msg: Msg) -> Option<Result<Seen, Error>> {
         let (reply, receiver) = oneshot::channel();
 
         if self.sender.send(Envelope { msg, reply }).await.is_err() {
@@ -236,15 +1098,72 @@ impl Search {
         .into_error(err)))
     }
 
-    pub async fn peek(&mut self) -> Result<Option<Arc<Post>>, Error> {
-        self.fetch(Msg::Peek).await.transpose()
-    }
-
-    pub async fn next(&mut self) -> Result<Option<Arc<Post>>, Error> {
+    pub async fn next(&mut self) -> Result<Option<Seen>, Error> {
         self.fetch(Msg::Advance).await.transpose()
     }
 
-    pub async fn prev(&mut self) -> Result<Option<Arc<Post>>, Error> {
+    pub async fn prev(&mut self) -> Result<Option<Seen>, Error> {
         self.fetch(Msg::Rewind).await.transpose()
     }
+
+    /// Where this search is up to, as of the last reply.
+    pub fn snapshot(&self) -> Snapshot {
+        self.shared.lock().unwrap().snapshot.clone()
+    }
+
+    /// How many posts were skipped for having already come up.
+    pub fn duplicates(&self) -> usize {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// How many posts were skipped for matching the blacklist.
+    pub fn blacklisted(&self) -> usize {
+        self.blacklisted.load(Ordering::Relaxed)
+    }
+
+    /// Posts after the current one that have already been fetched, as of the
+    /// last reply.
+    pub fn upcoming(&self) -> Vec<Arc<Post>> {
+        self.shared.lock().unwrap().upcoming.clone()
+    }
+
+    /// Remembers `choice` for `post_id`, so it's returned when rewinding.
+    pub async fn record(&mut self, post_id: u64, choice: Choice) -> Result<(), Error> {
+        self.fetch(Msg::Record(post_id, choice))
+            .await
+            .transpose()
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_newest_and_current() {
+        let seen: Vec<_> = (0..10).map(|id| (id, None)).collect();
+
+        let mut snapshot = Snapshot {
+            seen: seen.clone(),
+            index: 10,
+        };
+        snapshot.truncate(4);
+        assert_eq!(snapshot.seen, seen[6..]);
+        assert_eq!(snapshot.index, 4);
+
+        // Rewound to the third post, which stays.
+        let mut snapshot = Snapshot {
+            seen: seen.clone(),
+            index: 3,
+        };
+        snapshot.truncate(4);
+        assert_eq!(snapshot.seen, seen[2..]);
+        assert_eq!(snapshot.index, 1);
+
+        let mut snapshot = Snapshot { seen, index: 10 };
+        snapshot.truncate(20);
+        assert_eq!(snapshot.seen.len(), 10);
+        assert_eq!(snapshot.index, 10);
+    }
 }
@@ -0,0 +1,457 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use futures::{StreamExt, TryStreamExt};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use rs621::client::Client;
+use rs621::post::{Post, Query, VoteDir, VoteMethod};
+
+use reqwest::{RequestBuilder, Response};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use snafu::{IntoError, ResultExt};
+
+use super::{
+    ApiSnafu, Backend, Error, HttpSnafu, NoAccountSnafu, Page, Pool, PostSet, Problem, Tag,
+    TagCategory, PAGE_SIZE, TAG_LIMIT,
+};
+use crate::platform::{BoxFuture, BoxStream};
+
+/// Characters left as they are when a username goes into a path.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// [`Backend`] for e621 and the sites running the same software.
+#[derive(Debug)]
+pub struct E621 {
+    client: Client,
+
+    /// For the endpoints [`Client`] doesn't cover.
+    http: reqwest::Client,
+    base_url: String,
+    user_agent: String,
+    username: String,
+    api_key: String,
+}
+
+impl E621 {
+    pub fn new<U, K>(base_url: &str, creator: &str, username: U, api_key: K) -> Self
+    where
+        U: Into<String>,
+        K: Into<String>,
+    {
+        let user_agent = format!(
+            "{}/{} (by {})",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            creator,
+        );
+
+        // TODO: When in a browser, set the `_client` query parameter.
+        let mut client = Client::new(base_url, user_agent.clone()).unwrap();
+
+        let username = username.into();
+        let api_key = api_key.into();
+        client.login(username.clone(), api_key.clone());
+
+        Self {
+            client,
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            user_agent,
+            username,
+            api_key,
+        }
+    }
+
+    /// A GET for `path`, without credentials.
+    fn get(&self, path: &str, query: &[(&str, &str)]) -> RequestBuilder {
+        // Browsers don't let us set the user agent, so use `_client` instead.
+        self.http
+            .get(format!("{}{}", self.base_url, path))
+            .query(&[("_client", self.user_agent.as_str())])
+            .query(query)
+    }
+
+    /// Fetches and deserializes `path` (like `/users/123.json`) directly.
+    async fn get_json<T>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        action: String,
+    ) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let request = self
+            .get(path, query)
+            .basic_auth(&self.username, Some(&self.api_key));
+        read_json(request, action).await
+    }
+
+    /// The logged in user's `/users/<name>.json`.
+    fn user_path(&self) -> String {
+        let name = utf8_percent_encode(&self.username, SEGMENT);
+        format!("/users/{name}.json")
+    }
+
+    /// Posts `form` to `path`, ignoring whatever comes back.
+    async fn post_form(
+        &self,
+        path: &str,
+        form: &[(&str, String)],
+        action: String,
+    ) -> Result<(), Error> {
+        self.http
+            .post(format!("{}{}", self.base_url, path))
+            .query(&[("_client", self.user_agent.as_str())])
+            .form(form)
+            .basic_auth(&self.username, Some(&self.api_key))
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map(|_| ())
+            .context(HttpSnafu { action })
+    }
+}
+
+/// Sends `request` and deserializes what comes back.
+async fn read_json<T>(request: RequestBuilder, action: String) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let response = request
+        .send()
+        .await
+        .and_then(Response::error_for_status)
+        .with_context(|_| HttpSnafu {
+            action: action.clone(),
+        })?;
+
+    response.json().await.context(HttpSnafu { action })
+}
+
+#[derive(Debug, Deserialize)]
+struct Autocomplete {
+    name: String,
+    category: TagCategory,
+    post_count: u64,
+    /// The alias that matched, if it was an alias that matched.
+    #[serde(default)]
+    antecedent_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Posts {
+    posts: Vec<Post>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RelatedTags {
+    #[serde(default)]
+    tags: Vec<RelatedTag>,
+}
+
+/// Older versions of the site send `[name, category]` pairs.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RelatedTag {
+    Pair(String, u64),
+    Named { name: String },
+}
+
+impl RelatedTag {
+    fn into_name(self) -> String {
+        match self {
+            RelatedTag::Pair(name, _) => name,
+            RelatedTag::Named { name } => name,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    #[serde(default)]
+    blacklisted_tags: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserName {
+    name: String,
+}
+
+impl Backend for E621 {
+    fn post_search(&self, terms: Vec<String>) -> BoxStream<'_, Result<Post, Error>> {
+        let terms: Vec<&str> = terms.iter().map(String::as_str).collect();
+        let query = Query::from(terms.as_slice());
+
+        let stream = self
+            .client
+            .post_search(query)
+            .enumerate()
+            .map(|(index, result)| {
+                result.map_err(|e| {
+                    ApiSnafu {
+                        action: format!("search post {index}"),
+                    }
+                    .into_error(e)
+                })
+            });
+
+        Box::pin(stream)
+    }
+
+    fn post_page(&self, terms: Vec<String>, page: Page) -> BoxFuture<'_, Result<Vec<Post>, Error>> {
+        Box::pin(async move {
+            let mut tags = terms.join(" ");
+            let page = match page {
+                Page::Number(n) => n.to_string(),
+                Page::Before(id) => format!("b{id}"),
+                Page::After(id) => format!("a{id}"),
+                Page::Following { key, value, id } => {
+                    tags.push_str(&format!(" ( ~{key}:<{value} ~( {key}:{value} id:<{id} ) )"));
+                    "1".to_owned()
+                }
+            };
+            let limit = PAGE_SIZE.to_string();
+            let query = [
+                ("tags", tags.as_str()),
+                ("page", page.as_str()),
+                ("limit", limit.as_str()),
+            ];
+
+            let found: Posts = self
+                .get_json("/posts.json", &query, format!("search page {page}"))
+                .await?;
+            Ok(found.posts)
+        })
+    }
+
+    fn post(&self, post_id: u64) -> BoxFuture<'_, Result<Option<Post>, Error>> {
+        let id = format!("id:{post_id}");
+        let query = Query::from(&[id.as_str()][..]);
+
+        Box::pin(async move {
+            Box::pin(self.client.post_search(query))
+                .try_next()
+                .await
+                .with_context(|_| ApiSnafu {
+                    action: format!("fetch #{post_id}"),
+                })
+        })
+    }
+
+    fn favorite(&self, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            self.client
+                .post_favorite(post_id)
+                .await
+                .map(|_| ())
+                .with_context(|_| ApiSnafu {
+                    action: format!("fav #{post_id}"),
+                })
+        })
+    }
+
+    fn unfavorite(&self, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            self.client
+                .post_unfavorite(post_id)
+                .await
+                .with_context(|_| ApiSnafu {
+                    action: format!("unfav #{post_id}"),
+                })
+        })
+    }
+
+    fn vote(&self, post_id: u64, dir: VoteDir) -> BoxFuture<'_, Result<(), Error>> {
+        let action = match dir {
+            VoteDir::Up => format!("vote up #{post_id}"),
+            VoteDir::Down => format!("vote down #{post_id}"),
+        };
+
+        Box::pin(async move {
+            self.client
+                .post_vote(post_id, VoteMethod::Set, dir)
+                .await
+                .map(|_| ())
+                .with_context(|_| ApiSnafu { action })
+        })
+    }
+
+    fn tags(&self, needle: String) -> BoxFuture<'_, Result<Vec<Tag>, Error>> {
+        Box::pin(async move {
+            let query = [("search[name_matches]", needle.as_str()), ("expiry", "7")];
+            let found: Vec<Autocomplete> = self
+                .get_json(
+                    "/tags/autocomplete.json",
+                    &query,
+                    format!("find tags matching `{needle}`"),
+                )
+                .await?;
+
+            let tags = found
+                .into_iter()
+                .take(TAG_LIMIT)
+                .map(|t| Tag {
+                    name: t.name,
+                    category: t.category,
+                    post_count: t.post_count,
+                    alias: t.antecedent_name,
+                })
+                .collect();
+            Ok(tags)
+        })
+    }
+
+    fn related_tags(&self, tags: Vec<String>) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        Box::pin(async move {
+            let joined = tags.join(" ");
+            let query = [("search[query]", joined.as_str())];
+            let related: RelatedTags = self
+                .get_json(
+                    "/related_tag.json",
+                    &query,
+                    format!("find tags related to `{joined}`"),
+                )
+                .await?;
+
+            let names = related
+                .tags
+                .into_iter()
+                .map(RelatedTag::into_name)
+                .filter(|name| !tags.contains(name))
+                .collect();
+            Ok(names)
+        })
+    }
+
+    fn blacklist(&self) -> BoxFuture<'_, Result<String, Error>> {
+        Box::pin(async move {
+            let user: User = self
+                .get_json(&self.user_path(), &[], "load your blacklist".to_owned())
+                .await?;
+            Ok(user.blacklisted_tags)
+        })
+    }
+
+    fn check_login(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            // Favorites without a `user_id` are the logged in user's, so
+            // there are none to be had without working credentials.
+            let error = match self
+                .get_json::<Posts>("/favorites.json", &[("limit", "1")], "log in".to_owned())
+                .await
+            {
+                Err(e) if e.problem() == Problem::Unauthorized => e,
+                result => return result.map(|_| ()),
+            };
+
+            // Look the username up without the credentials that were just
+            // refused, to tell a wrong username apart from a wrong key.
+            let query = [
+                ("search[name_matches]", self.username.as_str()),
+                ("limit", "1"),
+            ];
+            let request = self.get("/users.json", &query);
+            let found = read_json::<Vec<UserName>>(request, "look up your account".to_owned())
+                .await
+                .map(|users| {
+                    users
+                        .iter()
+                        .any(|u| u.name.eq_ignore_ascii_case(&self.username))
+                });
+
+            match found {
+                Ok(false) => NoAccountSnafu {
+                    username: self.username.clone(),
+                }
+                .fail(),
+                _ => Err(error),
+            }
+        })
+    }
+
+    fn pool(&self, pool_id: u64) -> BoxFuture<'_, Result<Option<Pool>, Error>> {
+        Box::pin(async move {
+            let path = format!("/pools/{pool_id}.json");
+            match self
+                .get_json(&path, &[], format!("load pool #{pool_id}"))
+                .await
+            {
+                Ok(pool) => Ok(Some(pool)),
+                Err(e) if e.problem() == Problem::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn pool_search(&self, name: String) -> BoxFuture<'_, Result<Vec<Pool>, Error>> {
+        Box::pin(async move {
+            // Pool names use underscores where they're shown with spaces.
+            let pattern = format!("*{}*", name.trim().replace(' ', "_"));
+            let query = [
+                ("search[name_matches]", pattern.as_str()),
+                ("search[order]", "post_count"),
+                ("limit", "30"),
+            ];
+            self.get_json("/pools.json", &query, format!("find pools named `{name}`"))
+                .await
+        })
+    }
+
+    fn post_sets(&self) -> BoxFuture<'_, Result<Vec<PostSet>, Error>> {
+        Box::pin(async move {
+            let query = [
+                ("search[creator_name]", self.username.as_str()),
+                ("limit", "100"),
+            ];
+            self.get_json("/post_sets.json", &query, "load your sets".to_owned())
+                .await
+        })
+    }
+
+    fn set_add(&self, set_id: u64, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let path = format!("/post_sets/{set_id}/add_posts.json");
+            let form = [("post_ids[]", post_id.to_string())];
+            self.post_form(&path, &form, format!("add #{post_id} to set #{set_id}"))
+                .await
+        })
+    }
+
+    fn set_remove(&self, set_id: u64, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let path = format!("/post_sets/{set_id}/remove_posts.json");
+            let form = [("post_ids[]", post_id.to_string())];
+            self.post_form(
+                &path,
+                &form,
+                format!("remove #{post_id} from set #{set_id}"),
+            )
+            .await
+        })
+    }
+}
@@ -0,0 +1,474 @@
+/*
+ * Yiffy.Fun
+ *
+ * Copyright (C) 2024 Playful KittyKat
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use futures::StreamExt;
+
+use rs621::post::{Post, VoteDir};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use super::{
+    co_occurring, Backend, Error, Page, Pool, PostSet, Tag, TagCategory, PAGE_SIZE, TAG_LIMIT,
+};
+use crate::platform::{BoxFuture, BoxStream};
+
+const POSTS: &str = include_str!("../assets/fixtures/posts.json");
+const TAGS: &str = include_str!("../assets/fixtures/tags.json");
+const ALIASES: &str = include_str!("../assets/fixtures/aliases.json");
+const POOLS: &str = include_str!("../assets/fixtures/pools.json");
+
+#[derive(Debug, Deserialize)]
+struct FixtureTag {
+    name: String,
+    category: TagCategory,
+    post_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct FixtureAlias {
+    antecedent_name: String,
+    consequent_name: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    votes: HashMap<u64, VoteDir>,
+    favorites: HashSet<u64>,
+    sets: Vec<PostSet>,
+    /// Posts that have come up in a search, for counting related tags.
+    served: HashSet<u64>,
+}
+
+/// [`Backend`] serving a fixed set of posts and tags without touching the
+/// network.
+///
+/// Votes and favorites are remembered for the life of the backend, so terms
+/// like `-voted:anything` behave the way they do on e621.
+#[derive(Debug)]
+pub struct Memory {
+    posts: Vec<Value>,
+    tags: Vec<FixtureTag>,
+    aliases: Vec<FixtureAlias>,
+    pools: Vec<Pool>,
+    state: Mutex<State>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    /// Creates a backend populated with the bundled fixtures.
+    pub fn new() -> Self {
+        let posts: Vec<Value> = serde_json::from_str(POSTS).expect("fixture posts are valid JSON");
+        let tags = serde_json::from_str(TAGS).expect("fixture tags are valid JSON");
+        let aliases = serde_json::from_str(ALIASES).expect("fixture aliases are valid JSON");
+        let pools = serde_json::from_str(POOLS).expect("fixture pools are valid JSON");
+
+        for post in &posts {
+            serde_json::from_value::<Post>(post.clone()).expect("fixture posts are valid posts");
+        }
+
+        let sets = ["wallpapers", "reference"]
+            .into_iter()
+            .zip(1..)
+            .map(|(name, id)| PostSet {
+                id,
+                name: name.to_owned(),
+                post_ids: Vec::new(),
+            })
+            .collect();
+
+        Self {
+            posts,
+            tags,
+            aliases,
+            pools,
+            state: Mutex::new(State {
+                sets,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+fn post_tags(post: &Value) -> impl Iterator<Item = &str> {
+    post["tags"]
+        .as_object()
+        .into_iter()
+        .flat_map(|c| c.values())
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+fn has_tag(post: &Value, tag: &str) -> bool {
+    post_tags(post).any(|t| t == tag)
+}
+
+fn matches(post: &Value, state: &State, term: &str) -> bool {
+    let (negate, term) = match term.strip_prefix('-') {
+        Some(t) => (true, t),
+        None => (false, term),
+    };
+
+    let id = post["id"].as_u64().unwrap_or_default();
+
+    let hit = match term.split_once(':') {
+        Some(("id", v)) => v.split(',').any(|v| v.parse() == Ok(id)),
+        Some(("voted", _)) => state.votes.contains_key(&id),
+        Some(("favoritedby", _)) => state.favorites.contains(&id),
+        Some(("type", v)) => post["file"]["ext"] == v,
+        Some(("pool", v)) => post["pools"]
+            .as_array()
+            .map_or(false, |p| p.iter().any(|p| p.as_u64() == v.parse().ok())),
+        Some(("rating", v)) => post["rating"].as_str().map_or(false, |r| v.starts_with(r)),
+
+        // Anything else (ordering, scores, etc.) never excludes a post.
+        Some(_) => return true,
+
+        None => has_tag(post, term),
+    };
+
+    hit != negate
+}
+
+impl Memory {
+    /// Every post matching `terms`, in fixture order.
+    fn find(&self, terms: &[String]) -> Vec<Post> {
+        let state = self.state.lock().unwrap();
+
+        let (any_of, all_of): (Vec<_>, Vec<_>) = terms
+            .iter()
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty())
+            .partition(|t| t.starts_with('~'));
+
+        self.posts
+            .iter()
+            .filter(|p| all_of.iter().all(|t| matches(p, &state, t)))
+            .filter(|p| any_of.is_empty() || any_of.iter().any(|t| matches(p, &state, &t[1..])))
+            .map(|p| {
+                let mut p = p.clone();
+                let id = p["id"].as_u64().unwrap_or_default();
+                p["is_favorited"] = Value::Bool(state.favorites.contains(&id));
+                serde_json::from_value::<Post>(p).unwrap()
+            })
+            .collect()
+    }
+
+    /// Notes that `posts` were handed out, for [`Backend::related_tags`].
+    fn serve<'a>(&self, posts: impl IntoIterator<Item = &'a Post>) {
+        let mut state = self.state.lock().unwrap();
+        state.served.extend(posts.into_iter().map(|p| p.id));
+    }
+}
+
+impl Backend for Memory {
+    fn post_search(&self, terms: Vec<String>) -> BoxStream<'_, Result<Post, Error>> {
+        let posts = self.find(&terms).into_iter().map(move |p| {
+            self.serve([&p]);
+            Ok(p)
+        });
+        Box::pin(futures::stream::iter(posts))
+    }
+
+    fn post_page(&self, terms: Vec<String>, page: Page) -> BoxFuture<'_, Result<Vec<Post>, Error>> {
+        let mut posts = self.find(&terms);
+
+        let order = terms.iter().find_map(|t| t.strip_prefix("order:"));
+        match order {
+            Some("id") => posts.sort_by_key(|p| p.id),
+            Some("score") => posts.sort_by_key(|p| Reverse((p.score.total, p.id))),
+            Some("favcount") => posts.sort_by_key(|p| Reverse((p.fav_count as i64, p.id))),
+            _ => posts.sort_by_key(|p| Reverse(p.id)),
+        }
+
+        let page: Vec<_> = match page {
+            Page::Number(n) => posts
+                .into_iter()
+                .skip(n.saturating_sub(1) as usize * PAGE_SIZE)
+                .take(PAGE_SIZE)
+                .collect(),
+            Page::Before(id) => posts
+                .into_iter()
+                .filter(|p| p.id < id)
+                .take(PAGE_SIZE)
+                .collect(),
+            Page::After(id) => posts
+                .into_iter()
+                .filter(|p| p.id > id)
+                .take(PAGE_SIZE)
+                .collect(),
+            Page::Following { key, value, id } => posts
+                .into_iter()
+                .filter(|p| {
+                    let v = match key {
+                        "favcount" => p.fav_count as i64,
+                        _ => p.score.total,
+                    };
+                    v < value || (v == value && p.id < id)
+                })
+                .take(PAGE_SIZE)
+                .collect(),
+        };
+
+        self.serve(&page);
+        Box::pin(futures::future::ok(page))
+    }
+
+    fn post(&self, post_id: u64) -> BoxFuture<'_, Result<Option<Post>, Error>> {
+        let mut found = self.post_search(vec![format!("id:{post_id}")]);
+        Box::pin(async move { found.next().await.transpose() })
+    }
+
+    fn favorite(&self, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        self.state.lock().unwrap().favorites.insert(post_id);
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn unfavorite(&self, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        self.state.lock().unwrap().favorites.remove(&post_id);
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn vote(&self, post_id: u64, dir: VoteDir) -> BoxFuture<'_, Result<(), Error>> {
+        self.state.lock().unwrap().votes.insert(post_id, dir);
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn tags(&self, needle: String) -> BoxFuture<'_, Result<Vec<Tag>, Error>> {
+        let needle = needle.trim().to_lowercase();
+
+        let named = self
+            .tags
+            .iter()
+            .filter(|t| t.name.starts_with(&needle))
+            .map(|t| (t, None));
+
+        let aliased = self
+            .aliases
+            .iter()
+            .filter(|a| a.antecedent_name.starts_with(&needle))
+            .filter_map(|a| {
+                let tag = self.tags.iter().find(|t| t.name == a.consequent_name)?;
+                Some((tag, Some(a.antecedent_name.clone())))
+            });
+
+        let mut found: Vec<_> = named.chain(aliased).collect();
+        found.sort_by_key(|(t, _)| Reverse(t.post_count));
+
+        let tags = found
+            .into_iter()
+            .take(TAG_LIMIT)
+            .map(|(t, alias)| Tag {
+                name: t.name.clone(),
+                category: t.category,
+                post_count: t.post_count,
+                alias,
+            })
+            .collect();
+        Box::pin(futures::future::ok(tags))
+    }
+
+    fn related_tags(&self, tags: Vec<String>) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        let state = self.state.lock().unwrap();
+
+        // Count how often each tag shows up next to `tags` on the posts that
+        // have already been served.
+        let served = self
+            .posts
+            .iter()
+            .filter(|p| {
+                p["id"]
+                    .as_u64()
+                    .map_or(false, |id| state.served.contains(&id))
+            })
+            .map(|p| post_tags(p).collect());
+
+        let names = co_occurring(served, &tags);
+        Box::pin(futures::future::ok(names))
+    }
+
+    fn blacklist(&self) -> BoxFuture<'_, Result<String, Error>> {
+        Box::pin(futures::future::ok("rating:e\nscore:<0\n".to_owned()))
+    }
+
+    fn check_login(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn pool(&self, pool_id: u64) -> BoxFuture<'_, Result<Option<Pool>, Error>> {
+        let pool = self.pools.iter().find(|p| p.id == pool_id).cloned();
+        Box::pin(futures::future::ok(pool))
+    }
+
+    fn pool_search(&self, name: String) -> BoxFuture<'_, Result<Vec<Pool>, Error>> {
+        let name = name.trim().replace(' ', "_").to_lowercase();
+        let pools = self
+            .pools
+            .iter()
+            .filter(|p| p.name.to_lowercase().contains(&name))
+            .cloned()
+            .collect();
+        Box::pin(futures::future::ok(pools))
+    }
+
+    fn post_sets(&self) -> BoxFuture<'_, Result<Vec<PostSet>, Error>> {
+        let sets = self.state.lock().unwrap().sets.clone();
+        Box::pin(futures::future::ok(sets))
+    }
+
+    fn set_add(&self, set_id: u64, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(set) = state.sets.iter_mut().find(|s| s.id == set_id) {
+            if !set.post_ids.contains(&post_id) {
+                set.post_ids.push(post_id);
+            }
+        }
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn set_remove(&self, set_id: u64, post_id: u64) -> BoxFuture<'_, Result<(), Error>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(set) = state.sets.iter_mut().find(|s| s.id == set_id) {
+            set.post_ids.retain(|p| *p != post_id);
+        }
+        Box::pin(futures::future::ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::blacklist::Blacklist;
+    use crate::yiff::{Order, Search, Snapshot, Yiff};
+
+    fn yiff() -> Yiff<Memory> {
+        Yiff::with_backend(Memory::new())
+    }
+
+    /// Lets the search's background task catch up.
+    async fn settle() {
+        for _ in 0..32 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn next_id(search: &mut Search) -> Option<u64> {
+        search.next().await.unwrap().map(|s| s.post.id)
+    }
+
+    async fn prev_id(search: &mut Search) -> Option<u64> {
+        search.prev().await.unwrap().map(|s| s.post.id)
+    }
+
+    async fn remaining(search: &mut Search) -> Vec<u64> {
+        let mut ids = Vec::new();
+        while let Some(id) = next_id(search).await {
+            ids.push(id);
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn evicted_posts_are_fetched_again() {
+        let mut search = yiff()
+            .with_history_limit(1)
+            .search(Vec::<String>::new(), Order::Random);
+
+        for id in 1000..1005 {
+            assert_eq!(next_id(&mut search).await, Some(id));
+        }
+        for id in (1000..1004).rev() {
+            assert_eq!(prev_id(&mut search).await, Some(id));
+        }
+        assert_eq!(prev_id(&mut search).await, None);
+
+        for id in 1000..1006 {
+            assert_eq!(next_id(&mut search).await, Some(id));
+        }
+
+        let snapshot = search.snapshot();
+        assert_eq!(snapshot.index, 6);
+        assert_eq!(snapshot.seen.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn fetches_ahead() {
+        let mut search = yiff()
+            .with_lookahead(3)
+            .search(Vec::<String>::new(), Order::Random);
+
+        assert_eq!(next_id(&mut search).await, Some(1000));
+        settle().await;
+
+        let upcoming: Vec<_> = search.upcoming().iter().map(|p| p.id).collect();
+        assert_eq!(upcoming, [1001, 1002, 1003]);
+
+        assert_eq!(next_id(&mut search).await, Some(1001));
+    }
+
+    #[tokio::test]
+    async fn skips_posts_already_seen() {
+        let snapshot = Snapshot {
+            seen: vec![(1003, None)],
+            index: 1,
+        };
+        let mut search = yiff().resume(Vec::<String>::new(), Order::Random, snapshot);
+
+        let ids = remaining(&mut search).await;
+        assert_eq!(ids.iter().filter(|id| **id == 1003).count(), 1);
+        assert_eq!(ids.len(), 12);
+        assert_eq!(search.duplicates(), 0);
+    }
+
+    #[tokio::test]
+    async fn reads_pools_in_order() {
+        let pool = Pool {
+            id: 1,
+            name: "comic".to_owned(),
+            post_ids: vec![1006, 1004, 9999, 1007, 1005],
+        };
+        let mut search = yiff().read(pool, Snapshot::default());
+
+        assert_eq!(remaining(&mut search).await, [1006, 1004, 1007, 1005]);
+    }
+
+    #[tokio::test]
+    async fn skips_blacklisted_posts() {
+        let mut search = yiff()
+            .with_blacklist(Blacklist::parse("rating:e\nscore:<0\n"))
+            .search(Vec::<String>::new(), Order::Random);
+
+        let ids = remaining(&mut search).await;
+        assert!(!ids.contains(&1007));
+        assert!(!ids.contains(&1008));
+        assert!(!ids.contains(&1011));
+        assert_eq!(ids.len(), 9);
+        assert_eq!(search.blacklisted(), 3);
+    }
+}